@@ -0,0 +1,324 @@
+use super::geometry::{NodeLocations, Polygon, ring_contains, ring_signed_area};
+
+use std::collections::HashMap;
+
+/// Source element of an area.
+#[derive(Clone, Copy)]
+pub enum AreaSource {
+    Way(i64),
+    Relation(i64),
+}
+
+/// Keys of closed ways which are areas.
+const AREA_KEYS: &[&str] = &[
+    "aeroway",
+    "amenity",
+    "building",
+    "building:part",
+    "craft",
+    "historic",
+    "landuse",
+    "leisure",
+    "man_made",
+    "military",
+    "natural",
+    "office",
+    "place",
+    "shop",
+    "tourism",
+    "water",
+];
+
+/// Keys of closed ways which are linear features (like roundabouts or fences), unless they're tagged `area=yes`.
+const LINEAR_KEYS: &[&str] = &["barrier", "highway", "power", "railway", "waterway"];
+
+/// Returns whether a closed way with the given tags is an area: it's either tagged `area=yes`, or has a key of areas
+/// and none of linear features, and isn't tagged `area=no`.
+pub fn is_area<'a>(tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> bool {
+    let (mut area, mut has_area_key, mut has_linear_key) = (None, false, false);
+
+    for (key, value) in tags {
+        match key {
+            "area" => area = Some(value == "yes"),
+            key if LINEAR_KEYS.contains(&key) => has_linear_key = true,
+            key if AREA_KEYS.contains(&key) => has_area_key = true,
+            _ => {}
+        }
+    }
+
+    area.unwrap_or(has_area_key && !has_linear_key)
+}
+
+/// Ways and multipolygon relations collected during the import, from which areas are built afterwards.
+///
+/// As the relations referencing a way follow it in the input, the node references of all ways are kept in memory,
+/// which takes roughly 8 bytes per reference and 50 bytes per way.
+///
+/// The data added since the last call of `release` can be discarded with `roll_back`, if its block is rolled back.
+#[derive(Default)]
+pub struct AreaCollector {
+    way_refs: HashMap<i64, Vec<i64>>,
+    closed_ways: Vec<i64>,
+    relations: Vec<(i64, Vec<(i64, bool)>)>,
+    /// Ways added since the last release, with the references they've replaced.
+    block_ways: Vec<(i64, Option<Vec<i64>>)>,
+    /// Number of closed ways and relations at the last release.
+    released_len: (usize, usize),
+}
+
+impl AreaCollector {
+    /// Adds a way with its node references. Closed ways become areas themselves if `is_area` is `true`.
+    pub fn add_way(&mut self, way_id: i64, refs: Vec<i64>, is_area: bool) {
+        if is_area && refs.len() >= 4 && refs.first() == refs.last() {
+            self.closed_ways.push(way_id);
+        }
+
+        let replaced = self.way_refs.insert(way_id, refs);
+        self.block_ways.push((way_id, replaced));
+    }
+
+    /// Adds a multipolygon relation with its way members as `(way_id, is_inner)` pairs.
+    pub fn add_relation(&mut self, relation_id: i64, way_members: Vec<(i64, bool)>) {
+        self.relations.push((relation_id, way_members));
+    }
+
+    /// Keeps the data added since the last release.
+    pub fn release(&mut self) {
+        self.block_ways.clear();
+        self.released_len = (self.closed_ways.len(), self.relations.len());
+    }
+
+    /// Discards the data added since the last release.
+    pub fn roll_back(&mut self) {
+        for (way_id, replaced) in self.block_ways.drain(..).rev() {
+            match replaced {
+                Some(refs) => self.way_refs.insert(way_id, refs),
+                None => self.way_refs.remove(&way_id),
+            };
+        }
+
+        self.closed_ways.truncate(self.released_len.0);
+        self.relations.truncate(self.released_len.1);
+    }
+
+    /// Builds all areas, calling `on_area` for each successfully assembled one and `on_error` for each failure.
+    pub fn build<OnArea, OnError>(
+        &self,
+        node_locations: &NodeLocations,
+        mut on_area: OnArea,
+        mut on_error: OnError,
+    ) -> anyhow::Result<()>
+    where
+        OnArea: FnMut(AreaSource, &[Polygon]) -> anyhow::Result<()>,
+        OnError: FnMut(AreaSource, &str) -> anyhow::Result<()>,
+    {
+        for way_id in &self.closed_ways {
+            let source = AreaSource::Way(*way_id);
+
+            match resolve_ring(&self.way_refs[way_id], node_locations) {
+                Ok(ring) => on_area(
+                    source,
+                    &[Polygon {
+                        outer: ring,
+                        inners: Vec::new(),
+                    }],
+                )?,
+                Err(error) => on_error(source, &error)?,
+            }
+        }
+
+        for (relation_id, way_members) in &self.relations {
+            let source = AreaSource::Relation(*relation_id);
+
+            match self.build_relation(way_members, node_locations) {
+                Ok(polygons) => on_area(source, &polygons)?,
+                Err(error) => on_error(source, &error)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build_relation(
+        &self,
+        way_members: &[(i64, bool)],
+        node_locations: &NodeLocations,
+    ) -> Result<Vec<Polygon>, String> {
+        let mut outer_ways = Vec::new();
+        let mut inner_ways = Vec::new();
+
+        for (way_id, is_inner) in way_members {
+            let refs = self
+                .way_refs
+                .get(way_id)
+                .ok_or_else(|| format!("member way {way_id} is missing"))?;
+
+            if *is_inner {
+                inner_ways.push(refs.as_slice());
+            } else {
+                outer_ways.push(refs.as_slice());
+            }
+        }
+
+        let resolve_rings = |ways: &[&[i64]]| -> Result<Vec<Vec<(i64, i64)>>, String> {
+            assemble_rings(ways)?
+                .iter()
+                .map(|ring| resolve_ring(ring, node_locations))
+                .collect()
+        };
+
+        let mut polygons: Vec<Polygon> = resolve_rings(&outer_ways)?
+            .into_iter()
+            .map(|outer| Polygon {
+                outer,
+                inners: Vec::new(),
+            })
+            .collect();
+
+        if polygons.is_empty() {
+            return Err("no outer rings".to_string());
+        }
+
+        // With nested outer rings (like an island in a lake), an inner ring belongs to the smallest one containing it
+        for inner in resolve_rings(&inner_ways)? {
+            let polygon = polygons
+                .iter_mut()
+                .filter(|polygon| ring_contains(&polygon.outer, inner[0]))
+                .min_by_key(|polygon| ring_signed_area(&polygon.outer).abs())
+                .ok_or_else(|| "inner ring is outside of all outer rings".to_string())?;
+
+            polygon.inners.push(inner);
+        }
+
+        Ok(polygons)
+    }
+}
+
+/// Joins `ways` at their shared end nodes into closed rings of node IDs.
+fn assemble_rings(ways: &[&[i64]]) -> Result<Vec<Vec<i64>>, String> {
+    let mut segments: Vec<&[i64]> = ways.iter().copied().filter(|way| !way.is_empty()).collect();
+    let mut rings = Vec::new();
+
+    while let Some(first) = segments.pop() {
+        let mut ring = first.to_vec();
+
+        while ring.len() < 2 || ring.first() != ring.last() {
+            let last = *ring.last().expect("non-empty ring");
+
+            if let Some(i) = segments.iter().position(|segment| segment[0] == last) {
+                ring.extend_from_slice(&segments.swap_remove(i)[1..]);
+            } else if let Some(i) = segments.iter().position(|segment| segment[segment.len() - 1] == last) {
+                ring.extend(segments.swap_remove(i).iter().rev().skip(1));
+            } else {
+                return Err(format!("ring starting at node {} is not closed", ring[0]));
+            }
+        }
+
+        rings.push(ring);
+    }
+
+    Ok(rings)
+}
+
+/// Looks up the coordinates of a closed ring's nodes.
+fn resolve_ring(ring: &[i64], node_locations: &NodeLocations) -> Result<Vec<(i64, i64)>, String> {
+    if ring.len() < 4 {
+        return Err(format!("ring starting at node {} has less than 4 nodes", ring[0]));
+    }
+
+    ring.iter()
+        .map(|node_id| {
+            node_locations
+                .get(node_id)
+                .copied()
+                .ok_or_else(|| format!("node {node_id} is missing"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod area_tests {
+    use super::*;
+
+    #[test]
+    fn ring_assembly() {
+        let rings = assemble_rings(&[&[1, 2, 3], &[5, 4, 3], &[5, 1]]).unwrap();
+        assert_eq!(rings, vec![vec![5, 1, 2, 3, 4, 5]]);
+
+        assert!(assemble_rings(&[&[1, 2, 3], &[3, 4]]).is_err());
+    }
+
+    #[test]
+    fn area_tags() {
+        assert!(is_area([("building", "yes")]));
+        assert!(is_area([("name", "Park"), ("leisure", "park")]));
+        assert!(is_area([("highway", "pedestrian"), ("area", "yes")]));
+        assert!(is_area([("area", "yes"), ("barrier", "fence")]));
+
+        assert!(!is_area([]));
+        assert!(!is_area([("name", "Ring")]));
+        assert!(!is_area([("highway", "residential"), ("junction", "roundabout")]));
+        assert!(!is_area([("barrier", "fence"), ("landuse", "meadow")]));
+        assert!(!is_area([("building", "yes"), ("area", "no")]));
+    }
+
+    #[test]
+    fn nested_rings() {
+        let mut node_locations = NodeLocations::default();
+        let mut areas = AreaCollector::default();
+
+        // Squares of decreasing size around the same center, from node IDs `first` to `first + 3`
+        for (way_id, first, size) in [(1, 1, 100), (2, 5, 80), (3, 9, 60), (4, 13, 40)] {
+            let (min, max) = (100 - size, 100 + size);
+            for (offset, coord) in [(min, min), (min, max), (max, max), (max, min)].into_iter().enumerate() {
+                node_locations.insert(first + offset as i64, coord);
+            }
+
+            areas.add_way(way_id, vec![first, first + 1, first + 2, first + 3, first], false);
+        }
+
+        // An island (outer 3) with a pond (inner 4) in a lake (inner 2), in both orders of the outer rings
+        for members in [
+            [(1, false), (2, true), (3, false), (4, true)],
+            [(3, false), (4, true), (1, false), (2, true)],
+        ] {
+            let polygons = areas.build_relation(&members, &node_locations).unwrap();
+
+            // Rings are identified by their minimum coordinates
+            let mut rings: Vec<_> = polygons
+                .iter()
+                .map(|polygon| {
+                    let inners: Vec<_> = polygon.inners.iter().map(|inner| inner.iter().min().copied()).collect();
+                    (polygon.outer.iter().min().copied(), inners)
+                })
+                .collect();
+            rings.sort();
+
+            assert_eq!(
+                rings,
+                [
+                    (Some((0, 0)), vec![Some((20, 20))]),
+                    (Some((40, 40)), vec![Some((60, 60))])
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn rolled_back_ways() {
+        let mut areas = AreaCollector::default();
+
+        areas.add_way(1, vec![1, 2, 3, 1], true);
+        areas.release();
+
+        areas.add_way(1, vec![4, 5, 6, 4], true);
+        areas.add_way(2, vec![1, 2, 3, 1], true);
+        areas.add_relation(3, vec![(2, false)]);
+        areas.roll_back();
+
+        assert_eq!(areas.way_refs.len(), 1);
+        assert_eq!(areas.way_refs[&1], [1, 2, 3, 1]);
+        assert_eq!(areas.closed_ways, [1]);
+        assert!(areas.relations.is_empty());
+    }
+}
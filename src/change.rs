@@ -0,0 +1,708 @@
+use anyhow::{Context, bail};
+use quick_xml::events::{BytesStart, Event};
+use rusqlite::{CachedStatement, ToSql, Transaction, params};
+
+use super::config::{Config, TableConfig};
+use super::filter::ElementType;
+use super::{InsertStatements, insert_tag_row, prepare_insert_statements, tags_json};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Member {
+    pub element_type: ElementType,
+    pub id: i64,
+    pub role: String,
+}
+
+/// A created, modified or deleted element of an [OsmChange](https://wiki.openstreetmap.org/wiki/OsmChange) file.
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub action: Action,
+    pub element_type: ElementType,
+    pub id: i64,
+    pub version: Option<i64>,
+    /// Milliseconds since the Unix epoch, like the timestamps of the PBF import.
+    pub timestamp: Option<i64>,
+    pub changeset: Option<i64>,
+    pub user_id: Option<i64>,
+    pub user: Option<String>,
+    pub visible: Option<bool>,
+    /// Coordinates of nodes in nanodegrees.
+    pub coord: Option<(i64, i64)>,
+    pub tags: Vec<(String, String)>,
+    pub node_refs: Vec<i64>,
+    pub members: Vec<Member>,
+}
+
+fn parse_element_type(element_type: &str) -> anyhow::Result<ElementType> {
+    match element_type {
+        "node" => Ok(ElementType::Node),
+        "way" => Ok(ElementType::Way),
+        "relation" => Ok(ElementType::Relation),
+        _ => bail!("Invalid element type `{element_type}`"),
+    }
+}
+
+fn parse_degrees(degrees: &str) -> anyhow::Result<i64> {
+    let degrees: f64 = degrees.parse()?;
+    Ok((degrees * 1e9).round() as i64)
+}
+
+/// Parses an ISO 8601 timestamp like `2024-01-31T12:00:00Z` to milliseconds since the Unix epoch.
+pub fn parse_timestamp(timestamp: &str) -> anyhow::Result<i64> {
+    let invalid = || format!("Invalid timestamp `{timestamp}`");
+
+    let (date, time) = timestamp
+        .strip_suffix('Z')
+        .and_then(|timestamp| timestamp.split_once('T'))
+        .with_context(invalid)?;
+
+    let parse_fields = |s: &str, separator: char| -> anyhow::Result<[i64; 3]> {
+        let fields = s.split(separator).map(str::parse).collect::<Result<Vec<i64>, _>>();
+        fields
+            .ok()
+            .and_then(|fields| fields.try_into().ok())
+            .with_context(invalid)
+    };
+
+    let [year, month, day] = parse_fields(date, '-')?;
+    let [hours, minutes, seconds] = parse_fields(time, ':')?;
+    let seconds_of_day = hours * 3600 + minutes * 60 + seconds;
+
+    // Days since the epoch in the proleptic Gregorian calendar, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Ok((days * 86400 + seconds_of_day) * 1000)
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(attribute_key, _)| attribute_key == key)
+        .map(|(_, value)| value.as_str())
+}
+
+fn required_attribute<'a>(attributes: &'a [(String, String)], key: &str, element: &str) -> anyhow::Result<&'a str> {
+    attribute(attributes, key).with_context(|| format!("Missing `{key}` attribute of `{element}`"))
+}
+
+fn read_attributes(start: &BytesStart) -> anyhow::Result<Vec<(String, String)>> {
+    start
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute?;
+            let key = String::from_utf8(attribute.key.as_ref().to_vec())?;
+            let value = attribute.unescape_value()?.into_owned();
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn parse_element(action: Action, element_type: ElementType, attributes: &[(String, String)]) -> anyhow::Result<Change> {
+    let parse_optional = |key: &str| -> anyhow::Result<Option<i64>> {
+        attribute(attributes, key)
+            .map(|value| {
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid `{key}` attribute `{value}`"))
+            })
+            .transpose()
+    };
+
+    let id = required_attribute(attributes, "id", "element")?;
+    let id = id.parse().with_context(|| format!("Invalid element ID `{id}`"))?;
+
+    let coord = match (attribute(attributes, "lat"), attribute(attributes, "lon")) {
+        (Some(lat), Some(lon)) if element_type == ElementType::Node => Some((
+            parse_degrees(lat).with_context(|| format!("Invalid latitude of node {id}"))?,
+            parse_degrees(lon).with_context(|| format!("Invalid longitude of node {id}"))?,
+        )),
+        _ => None,
+    };
+
+    if action != Action::Delete && element_type == ElementType::Node && coord.is_none() {
+        bail!("Missing coordinates of node {id}");
+    }
+
+    Ok(Change {
+        action,
+        element_type,
+        id,
+        version: parse_optional("version")?,
+        timestamp: attribute(attributes, "timestamp").map(parse_timestamp).transpose()?,
+        changeset: parse_optional("changeset")?,
+        user_id: parse_optional("uid")?,
+        user: attribute(attributes, "user").map(str::to_string),
+        visible: attribute(attributes, "visible").map(|visible| visible == "true"),
+        coord,
+        tags: Vec::new(),
+        node_refs: Vec::new(),
+        members: Vec::new(),
+    })
+}
+
+/// Parses the contents of an [OsmChange](https://wiki.openstreetmap.org/wiki/OsmChange) file, returning the changes
+/// in file order.
+pub fn parse_changes<Input: BufRead>(input: Input) -> anyhow::Result<Vec<Change>> {
+    let mut reader = quick_xml::Reader::from_reader(input);
+    let mut buffer = Vec::new();
+
+    let mut changes = Vec::new();
+    let mut action = None;
+    let mut current: Option<Change> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buffer)
+            .with_context(|| format!("Invalid XML at position {}", reader.buffer_position()))?;
+
+        let (start, is_empty) = match &event {
+            Event::Start(start) => (start, false),
+            Event::Empty(start) => (start, true),
+            Event::End(end) => {
+                match end.local_name().as_ref() {
+                    b"create" | b"modify" | b"delete" => action = None,
+                    b"node" | b"way" | b"relation" => changes.extend(current.take()),
+                    _ => {}
+                }
+                buffer.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buffer.clear();
+                continue;
+            }
+        };
+
+        let name = String::from_utf8(start.local_name().as_ref().to_vec())?;
+        let attributes = read_attributes(start)?;
+
+        match name.as_str() {
+            "create" => action = Some(Action::Create),
+            "modify" => action = Some(Action::Modify),
+            "delete" => action = Some(Action::Delete),
+            "node" | "way" | "relation" => {
+                let action = action.with_context(|| format!("`{name}` outside of `create`, `modify` or `delete`"))?;
+                let change = parse_element(action, parse_element_type(&name)?, &attributes)?;
+
+                if is_empty {
+                    changes.push(change);
+                } else {
+                    current = Some(change);
+                }
+            }
+            "tag" => {
+                if let Some(change) = &mut current {
+                    change.tags.push((
+                        required_attribute(&attributes, "k", "tag")?.to_string(),
+                        required_attribute(&attributes, "v", "tag")?.to_string(),
+                    ));
+                }
+            }
+            "nd" => {
+                if let Some(change) = &mut current {
+                    let node_ref = required_attribute(&attributes, "ref", "nd")?;
+                    change.node_refs.push(
+                        node_ref
+                            .parse()
+                            .with_context(|| format!("Invalid node reference `{node_ref}`"))?,
+                    );
+                }
+            }
+            "member" => {
+                if let Some(change) = &mut current {
+                    let member_ref = required_attribute(&attributes, "ref", "member")?;
+                    change.members.push(Member {
+                        element_type: parse_element_type(required_attribute(&attributes, "type", "member")?)?,
+                        id: member_ref
+                            .parse()
+                            .with_context(|| format!("Invalid member reference `{member_ref}`"))?,
+                        role: attribute(&attributes, "role").unwrap_or_default().to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        buffer.clear();
+    }
+
+    Ok(changes)
+}
+
+/// Reads an OsmChange file, which is decompressed first if its extension is `.gz`.
+pub fn read_change_file(path: &Path) -> anyhow::Result<Vec<Change>> {
+    let file = File::open(path).with_context(|| format!("Failed to open change file `{path:?}`"))?;
+
+    let changes = if path.extension().is_some_and(|extension| extension == "gz") {
+        parse_changes(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        parse_changes(BufReader::new(file))
+    };
+
+    changes.with_context(|| format!("Failed to parse change file `{path:?}`"))
+}
+
+/// Statements removing all rows of an element, dependent tables first.
+struct DeleteStatements<'a> {
+    node: Vec<CachedStatement<'a>>,
+    way: Vec<CachedStatement<'a>>,
+    relation: Vec<CachedStatement<'a>>,
+}
+
+fn prepare_delete_statements<'a>(tr: &'a Transaction, config: &Config) -> rusqlite::Result<DeleteStatements<'a>> {
+    let prepare = |statements: &[(&str, Option<&TableConfig>)], dependent_table: &TableConfig| {
+        statements
+            .iter()
+            .filter(|(_, table)| !dependent_table.skip && table.is_some_and(|table| !table.skip))
+            .map(|(sql, _)| tr.prepare_cached(sql))
+            .collect::<rusqlite::Result<Vec<_>>>()
+    };
+
+    Ok(DeleteStatements {
+        node: prepare(
+            &[
+                (
+                    "DELETE FROM node_tags WHERE node_id = ?1",
+                    (!config.json_tags).then_some(&config.node_tags.table),
+                ),
+                ("DELETE FROM node_info WHERE node_id = ?1", Some(&config.node_info)),
+                ("DELETE FROM nodes_rtree WHERE id = ?1", config.nodes_rtree.as_ref()),
+                ("DELETE FROM nodes WHERE id = ?1", Some(&config.nodes)),
+            ],
+            &config.nodes,
+        )?,
+        way: prepare(
+            &[
+                (
+                    "DELETE FROM way_tags WHERE way_id = ?1",
+                    (!config.json_tags).then_some(&config.way_tags.table),
+                ),
+                ("DELETE FROM way_info WHERE way_id = ?1", Some(&config.way_info)),
+                ("DELETE FROM way_refs WHERE way_id = ?1", Some(&config.way_refs)),
+                ("DELETE FROM ways WHERE id = ?1", Some(&config.ways)),
+            ],
+            &config.ways,
+        )?,
+        relation: prepare(
+            &[
+                (
+                    "DELETE FROM relation_tags WHERE relation_id = ?1",
+                    (!config.json_tags).then_some(&config.relation_tags.table),
+                ),
+                (
+                    "DELETE FROM relation_info WHERE relation_id = ?1",
+                    Some(&config.relation_info),
+                ),
+                (
+                    "DELETE FROM relation_members WHERE relation_id = ?1",
+                    Some(&config.relation_members),
+                ),
+                ("DELETE FROM relations WHERE id = ?1", Some(&config.relations)),
+            ],
+            &config.relations,
+        )?,
+    })
+}
+
+fn insert_change(change: &Change, config: &Config, stmts: &mut InsertStatements) -> rusqlite::Result<()> {
+    let (insert_element, insert_tag, insert_info) = match change.element_type {
+        ElementType::Node => (&mut stmts.node, &mut stmts.node_tag, &mut stmts.node_info),
+        ElementType::Way => (&mut stmts.way, &mut stmts.way_tag, &mut stmts.way_info),
+        ElementType::Relation => (&mut stmts.relation, &mut stmts.relation_tag, &mut stmts.relation_info),
+    };
+
+    if let Some(insert_element) = insert_element {
+        let tags = config.json_tags.then(|| {
+            let tags = change.tags.iter().map(|(key, value)| (key.as_str(), value.as_str()));
+            tags_json(config, change.element_type, tags)
+        });
+
+        let mut values: Vec<&dyn ToSql> = vec![&change.id];
+
+        if let Some((lat, lon)) = &change.coord {
+            values.extend_from_slice(&[lat, lon]);
+        }
+
+        if let Some(tags) = &tags {
+            values.push(tags);
+        }
+
+        insert_element.execute(&*values)?;
+    }
+
+    if let Some(insert_tag) = insert_tag {
+        for (key, value) in &change.tags {
+            if !config.skips_tag_key(change.element_type, key) {
+                insert_tag_row(insert_tag, &mut stmts.tag_dictionary, change.id, None, (key, value))?;
+            }
+        }
+    }
+
+    if let Some(insert_info) = insert_info {
+        if let (Some(insert_user), Some(user_id), Some(user)) = (&mut stmts.user, change.user_id, &change.user) {
+            insert_user.execute(params![user_id, user])?;
+        }
+
+        insert_info.execute(params![
+            change.id,
+            change.version,
+            change.timestamp,
+            change.changeset,
+            change.user_id,
+            change.user,
+            change.visible
+        ])?;
+    }
+
+    if let (Some(insert_node_rtree), Some((lat, lon))) = (&mut stmts.node_rtree, change.coord) {
+        insert_node_rtree.execute(params![change.id, lat, lon])?;
+    }
+
+    if let Some(insert_way_ref) = &mut stmts.way_ref {
+        for (i, node_id) in change.node_refs.iter().enumerate() {
+            insert_way_ref.execute(params![change.id, i as i64, node_id])?;
+        }
+    }
+
+    if let Some(insert_relation_member) = &mut stmts.relation_member {
+        for (i, member) in change.members.iter().enumerate() {
+            let member_id = |element_type: ElementType| (member.element_type == element_type).then_some(member.id);
+
+            insert_relation_member.execute(params![
+                change.id,
+                member_id(ElementType::Node),
+                member_id(ElementType::Way),
+                member_id(ElementType::Relation),
+                member.role,
+                i as i64
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of applied changes per action.
+#[derive(Default, Debug)]
+pub struct ChangeStats {
+    pub created: usize,
+    pub modified: usize,
+    pub deleted: usize,
+}
+
+/// Applies `changes` to a dump created with the same configuration, in file order.
+///
+/// The rows of each changed element are removed first, then created and modified elements are inserted like during
+/// the import. If a tag filter is configured, elements no longer matching it are removed.
+pub fn apply_changes(tr: &Transaction, config: &Config, changes: &[Change]) -> anyhow::Result<ChangeStats> {
+    if config.clip.is_some() || config.filter.as_ref().is_some_and(|filter| filter.add_dependencies) {
+        bail!("Changes can't be applied to dumps which are clipped or filtered with dependencies");
+    }
+
+    if config.history {
+        bail!("Changes can't be applied to history dumps");
+    }
+
+    if config.way_geometries.is_some()
+        || config.ways_rtree.is_some()
+        || config.areas.is_some()
+        || config.geopackage
+        || config.names_fts.is_some()
+    {
+        bail!("Changes can't be applied to dumps with way geometries, areas, GeoPackage features or name search");
+    }
+
+    let mut insert_stmts = prepare_insert_statements(tr, config)?;
+    let mut delete_stmts = prepare_delete_statements(tr, config)?;
+
+    let mut stats = ChangeStats::default();
+
+    for change in changes {
+        let delete_stmts = match change.element_type {
+            ElementType::Node => &mut delete_stmts.node,
+            ElementType::Way => &mut delete_stmts.way,
+            ElementType::Relation => &mut delete_stmts.relation,
+        };
+
+        for delete_stmt in delete_stmts {
+            delete_stmt.execute(params![change.id])?;
+        }
+
+        let is_selected = config.filter.as_ref().is_none_or(|filter| {
+            let tags: Vec<(&str, &str)> = change.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            filter.expressions.matches(change.element_type, &tags)
+        });
+
+        if change.action != Action::Delete && is_selected {
+            insert_change(change, config, &mut insert_stmts)?;
+        }
+
+        match change.action {
+            Action::Create => stats.created += 1,
+            Action::Modify => stats.modified += 1,
+            Action::Delete => stats.deleted += 1,
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod change_tests {
+    use super::*;
+
+    const CHANGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osmChange version="0.6" generator="test">
+  <create>
+    <node id="1" version="1" timestamp="2024-01-31T12:00:00Z" uid="7" user="A &amp; B" changeset="3" lat="47.5" lon="19.04"/>
+    <way id="10" version="1" timestamp="2024-01-31T12:00:00Z" uid="7" user="A &amp; B" changeset="3">
+      <nd ref="1"/>
+      <nd ref="2"/>
+      <tag k="highway" v="residential"/>
+    </way>
+  </create>
+  <modify>
+    <node id="2" version="2" lat="-0.5" lon="0">
+      <tag k="name" v="Foo"/>
+      <tag k="note" v="bar"/>
+    </node>
+    <relation id="20" version="3">
+      <member type="way" ref="10" role="outer"/>
+      <member type="node" ref="1" role=""/>
+      <tag k="type" v="multipolygon"/>
+    </relation>
+  </modify>
+  <delete>
+    <node id="3" version="4"/>
+  </delete>
+</osmChange>
+"#;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(parse_timestamp("2024-01-31T12:00:00Z").unwrap(), 1_706_702_400_000);
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z").unwrap(), -1000);
+        assert!(parse_timestamp("2024-01-31 12:00:00").is_err());
+        assert!(parse_timestamp("2024-01T12:00:00Z").is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let changes = parse_changes(CHANGE.as_bytes()).unwrap();
+
+        assert_eq!(changes.len(), 5);
+
+        assert_eq!(changes[0].action, Action::Create);
+        assert_eq!(changes[0].coord, Some((47_500_000_000, 19_040_000_000)));
+        assert_eq!(changes[0].timestamp, Some(1_706_702_400_000));
+        assert_eq!(changes[0].user.as_deref(), Some("A & B"));
+        assert_eq!(changes[0].changeset, Some(3));
+
+        assert_eq!(changes[1].element_type, ElementType::Way);
+        assert_eq!(changes[1].node_refs, vec![1, 2]);
+        assert_eq!(
+            changes[1].tags,
+            vec![("highway".to_string(), "residential".to_string())]
+        );
+
+        assert_eq!(changes[2].action, Action::Modify);
+        assert_eq!(changes[2].coord, Some((-500_000_000, 0)));
+        assert_eq!(changes[2].tags.len(), 2);
+
+        assert_eq!(
+            changes[3].members,
+            vec![
+                Member {
+                    element_type: ElementType::Way,
+                    id: 10,
+                    role: "outer".to_string()
+                },
+                Member {
+                    element_type: ElementType::Node,
+                    id: 1,
+                    role: String::new()
+                }
+            ]
+        );
+
+        assert_eq!(changes[4].action, Action::Delete);
+        assert_eq!(changes[4].id, 3);
+        assert_eq!(changes[4].coord, None);
+
+        assert!(parse_changes(r#"<osmChange><node id="1" lat="0" lon="0"/></osmChange>"#.as_bytes()).is_err());
+        assert!(parse_changes(r#"<osmChange><create><node id="1"/></create></osmChange>"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+skip_tag_keys = ["note"]
+"#,
+        )
+        .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+
+        super::super::db::create_tables(&tr, &config).unwrap();
+        tr.execute_batch(
+            "INSERT INTO nodes (id, lat, lon) VALUES (2, 0, 0), (3, 1, 1);
+             INSERT INTO node_tags (node_id, key, value) VALUES (2, 'name', 'Old'), (3, 'name', 'Deleted');
+             INSERT INTO relations (id) VALUES (20);",
+        )
+        .unwrap();
+
+        let stats = apply_changes(&tr, &config, &parse_changes(CHANGE.as_bytes()).unwrap()).unwrap();
+        assert_eq!((stats.created, stats.modified, stats.deleted), (2, 2, 1));
+
+        let count = |sql: &str| tr.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap();
+
+        assert_eq!(count("SELECT COUNT(*) FROM nodes"), 2);
+        assert_eq!(count("SELECT lat FROM nodes WHERE id = 2"), -500_000_000);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM node_tags WHERE node_id = 2 AND value = 'Foo'"),
+            1
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM node_tags"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM way_refs WHERE way_id = 10"), 2);
+        assert_eq!(
+            count("SELECT member_way_id FROM relation_members WHERE sequence_id = 0"),
+            10
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM node_info WHERE node_id = 3"), 0);
+
+        tr.commit().unwrap();
+    }
+
+    #[test]
+    fn apply_json_tags() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+skip_tag_keys = ["note"]
+json_tags = true
+"#,
+        )
+        .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+
+        super::super::db::create_tables(&tr, &config).unwrap();
+        tr.execute_batch(
+            "INSERT INTO nodes (id, lat, lon, tags) VALUES (2, 0, 0, '{\"name\":\"Old\"}'), (3, 1, 1, '{}');
+             INSERT INTO relations (id, tags) VALUES (20, '{}');",
+        )
+        .unwrap();
+
+        apply_changes(&tr, &config, &parse_changes(CHANGE.as_bytes()).unwrap()).unwrap();
+
+        let tags = |sql: &str| tr.query_row(sql, [], |row| row.get::<_, String>(0)).unwrap();
+
+        assert_eq!(tags("SELECT tags FROM nodes WHERE id = 2"), r#"{"name":"Foo"}"#);
+        assert_eq!(tags("SELECT tags ->> 'highway' FROM ways WHERE id = 10"), "residential");
+        assert_eq!(tags("SELECT tags FROM nodes WHERE id = 1"), "{}");
+        assert!(tr.prepare("SELECT * FROM node_tags").is_err());
+    }
+
+    #[test]
+    fn apply_with_normalized_users() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+normalize_users = true
+"#,
+        )
+        .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+
+        super::super::db::create_tables(&tr, &config).unwrap();
+        tr.execute_batch(
+            "INSERT INTO nodes (id, lat, lon) VALUES (2, 0, 0), (3, 1, 1);
+             INSERT INTO relations (id) VALUES (20);",
+        )
+        .unwrap();
+
+        apply_changes(&tr, &config, &parse_changes(CHANGE.as_bytes()).unwrap()).unwrap();
+
+        let user: String = tr
+            .query_row("SELECT user FROM node_info_with_users WHERE node_id = 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(user, "A & B");
+
+        let user_count: i64 = tr
+            .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(user_count, 1);
+    }
+
+    #[test]
+    fn apply_with_normalized_tags() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+normalize_tags = true
+"#,
+        )
+        .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+
+        super::super::db::create_tables(&tr, &config).unwrap();
+        tr.execute_batch(
+            "INSERT INTO nodes (id, lat, lon) VALUES (2, 0, 0), (3, 1, 1);
+             INSERT INTO relations (id) VALUES (20);
+             INSERT INTO tag_keys (id, key) VALUES (5, 'name');",
+        )
+        .unwrap();
+
+        apply_changes(&tr, &config, &parse_changes(CHANGE.as_bytes()).unwrap()).unwrap();
+
+        let tags: Vec<(i64, String, String)> = tr
+            .prepare(
+                "SELECT t.key_id, s.key, s.value FROM node_tags t
+                 JOIN node_tags_with_strings s ON s.node_id = t.node_id AND s.key = 'name'
+                 WHERE t.node_id = 2 AND t.key_id = 5",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(tags, [(5, "name".to_string(), "Foo".to_string())]);
+
+        let key_count: i64 = tr
+            .query_row("SELECT COUNT(*) FROM tag_keys", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(key_count, 4);
+    }
+}
@@ -0,0 +1,220 @@
+use super::filter::ElementType;
+use super::geometry::BoundingBox;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Summary of the elements belonging to a changeset in the import.
+#[derive(Default)]
+pub struct ChangesetSummary {
+    pub user_id: Option<i32>,
+    pub user: Option<String>,
+    pub node_count: i64,
+    pub way_count: i64,
+    pub relation_count: i64,
+    /// Bounding box of the changeset's nodes.
+    pub bbox: Option<BoundingBox>,
+}
+
+impl ChangesetSummary {
+    fn merge(&mut self, other: ChangesetSummary) {
+        self.node_count += other.node_count;
+        self.way_count += other.way_count;
+        self.relation_count += other.relation_count;
+
+        if let Some(other_bbox) = other.bbox {
+            match &mut self.bbox {
+                Some(bbox) => {
+                    bbox.extend((other_bbox.min_lat, other_bbox.min_lon));
+                    bbox.extend((other_bbox.max_lat, other_bbox.max_lon));
+                }
+                None => self.bbox = Some(other_bbox),
+            }
+        }
+    }
+}
+
+/// Changeset summaries collected during the import, keyed by changeset ID.
+///
+/// Elements are added to the summaries of the current block, which are merged into the others by `release`, or
+/// discarded by `roll_back` if the block is rolled back.
+#[derive(Default)]
+pub struct Changesets {
+    summaries: HashMap<i64, ChangesetSummary>,
+    block_summaries: HashMap<i64, ChangesetSummary>,
+}
+
+impl Changesets {
+    /// Adds an element to the summary of its changeset. `user` is only called for the changeset's first element.
+    pub fn add<User>(
+        &mut self,
+        changeset_id: i64,
+        element_type: ElementType,
+        user_id: Option<i32>,
+        user: User,
+        coord: Option<(i64, i64)>,
+    ) -> anyhow::Result<()>
+    where
+        User: FnOnce() -> anyhow::Result<Option<String>>,
+    {
+        let summary = match self.block_summaries.entry(changeset_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let known = self.summaries.contains_key(&changeset_id);
+
+                entry.insert(ChangesetSummary {
+                    user_id,
+                    user: if known { None } else { user()? },
+                    ..Default::default()
+                })
+            }
+        };
+
+        match element_type {
+            ElementType::Node => summary.node_count += 1,
+            ElementType::Way => summary.way_count += 1,
+            ElementType::Relation => summary.relation_count += 1,
+        }
+
+        if let Some(coord) = coord {
+            match &mut summary.bbox {
+                Some(bbox) => bbox.extend(coord),
+                None => summary.bbox = BoundingBox::of(&[coord]),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges the summaries of the current block into the others.
+    pub fn release(&mut self) {
+        for (changeset_id, block_summary) in self.block_summaries.drain() {
+            match self.summaries.entry(changeset_id) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(block_summary),
+                Entry::Vacant(entry) => {
+                    entry.insert(block_summary);
+                }
+            }
+        }
+    }
+
+    /// Discards the summaries of the current block.
+    pub fn roll_back(&mut self) {
+        self.block_summaries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&i64, &ChangesetSummary)> {
+        self.summaries.iter()
+    }
+}
+
+#[cfg(test)]
+mod changesets_tests {
+    use super::*;
+
+    #[test]
+    fn summaries() {
+        let mut changesets = Changesets::default();
+
+        changesets
+            .add(
+                1,
+                ElementType::Node,
+                Some(7),
+                || Ok(Some("A".to_string())),
+                Some((10, -5)),
+            )
+            .unwrap();
+        changesets
+            .add(
+                1,
+                ElementType::Node,
+                Some(7),
+                || panic!("user of a known changeset"),
+                Some((-3, 20)),
+            )
+            .unwrap();
+        changesets
+            .add(1, ElementType::Way, Some(7), || unreachable!(), None)
+            .unwrap();
+        changesets
+            .add(2, ElementType::Relation, None, || Ok(None), None)
+            .unwrap();
+        changesets.release();
+
+        let summary = &changesets.summaries[&1];
+        assert_eq!(summary.user.as_deref(), Some("A"));
+        assert_eq!(
+            (summary.node_count, summary.way_count, summary.relation_count),
+            (2, 1, 0)
+        );
+        assert_eq!(
+            summary.bbox,
+            Some(BoundingBox {
+                min_lat: -3,
+                min_lon: -5,
+                max_lat: 10,
+                max_lon: 20
+            })
+        );
+
+        assert_eq!(changesets.summaries[&2].relation_count, 1);
+        assert_eq!(changesets.summaries[&2].bbox, None);
+    }
+
+    #[test]
+    fn blocks() {
+        let mut changesets = Changesets::default();
+
+        changesets
+            .add(
+                1,
+                ElementType::Node,
+                Some(7),
+                || Ok(Some("A".to_string())),
+                Some((0, 0)),
+            )
+            .unwrap();
+        changesets.release();
+
+        changesets
+            .add(
+                1,
+                ElementType::Node,
+                Some(7),
+                || panic!("user of a known changeset"),
+                Some((5, 5)),
+            )
+            .unwrap();
+        changesets
+            .add(2, ElementType::Way, Some(8), || Ok(Some("B".to_string())), None)
+            .unwrap();
+        changesets.roll_back();
+
+        changesets
+            .add(
+                1,
+                ElementType::Node,
+                Some(7),
+                || panic!("user of a known changeset"),
+                Some((-5, 10)),
+            )
+            .unwrap();
+        changesets.release();
+
+        assert_eq!(changesets.summaries.len(), 1);
+
+        let summary = &changesets.summaries[&1];
+        assert_eq!(summary.user.as_deref(), Some("A"));
+        assert_eq!(summary.node_count, 2);
+        assert_eq!(
+            summary.bbox,
+            Some(BoundingBox {
+                min_lat: -5,
+                min_lon: 0,
+                max_lat: 0,
+                max_lon: 10
+            })
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{Context, bail};
+
+use super::config::ClipConfig;
+use super::geometry::{BoundingBox, ring_contains};
+
+fn to_nanodegrees(degrees: f64) -> i64 {
+    (degrees * 1e9).round() as i64
+}
+
+/// Area of the input to import, with coordinates in nanodegrees.
+pub enum Region {
+    BoundingBox(BoundingBox),
+    Polygon {
+        bbox: BoundingBox,
+        outers: Vec<Vec<(i64, i64)>>,
+        holes: Vec<Vec<(i64, i64)>>,
+    },
+}
+
+impl Region {
+    pub fn from_config(config: &ClipConfig) -> anyhow::Result<Region> {
+        match (&config.bbox, &config.poly) {
+            (Some([left, bottom, right, top]), None) => Ok(Region::BoundingBox(BoundingBox {
+                min_lat: to_nanodegrees(*bottom),
+                min_lon: to_nanodegrees(*left),
+                max_lat: to_nanodegrees(*top),
+                max_lon: to_nanodegrees(*right),
+            })),
+            (None, Some(poly_path)) => {
+                let contents = std::fs::read_to_string(poly_path)
+                    .with_context(|| format!("Failed to read polygon file `{poly_path:?}`"))?;
+
+                parse_poly(&contents).with_context(|| format!("Failed to parse polygon file `{poly_path:?}`"))
+            }
+            _ => bail!("Exactly one of `bbox` and `poly` must be given for clipping"),
+        }
+    }
+
+    pub fn contains(&self, coord: (i64, i64)) -> bool {
+        let bbox_contains = |bbox: &BoundingBox| {
+            (bbox.min_lat..=bbox.max_lat).contains(&coord.0) && (bbox.min_lon..=bbox.max_lon).contains(&coord.1)
+        };
+
+        match self {
+            Region::BoundingBox(bbox) => bbox_contains(bbox),
+            Region::Polygon { bbox, outers, holes } => {
+                bbox_contains(bbox)
+                    && outers.iter().any(|ring| ring_contains(ring, coord))
+                    && !holes.iter().any(|ring| ring_contains(ring, coord))
+            }
+        }
+    }
+}
+
+/// Parses a polygon in [Osmosis polygon filter file format](https://wiki.openstreetmap.org/wiki/Osmosis/Polygon_Filter_File_Format).
+fn parse_poly(contents: &str) -> anyhow::Result<Region> {
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+
+    lines.next().context("Missing polygon name")?;
+
+    let mut outers = Vec::new();
+    let mut holes = Vec::new();
+
+    loop {
+        let section = lines.next().context("Missing END of file")?;
+
+        if section == "END" {
+            break;
+        }
+
+        let mut ring = Vec::new();
+
+        loop {
+            let line = lines
+                .next()
+                .with_context(|| format!("Missing END of section `{section}`"))?;
+
+            if line == "END" {
+                break;
+            }
+
+            let coord: Vec<f64> = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("Invalid coordinates `{line}`"))?;
+
+            let [lon, lat] = coord[..] else {
+                bail!("Invalid coordinates `{line}`");
+            };
+
+            ring.push((to_nanodegrees(lat), to_nanodegrees(lon)));
+        }
+
+        if ring.len() < 3 {
+            bail!("Section `{section}` has less than 3 coordinates");
+        }
+
+        if ring.first() != ring.last() {
+            ring.push(ring[0]);
+        }
+
+        if section.starts_with('!') {
+            holes.push(ring);
+        } else {
+            outers.push(ring);
+        }
+    }
+
+    let all_coords: Vec<(i64, i64)> = outers.iter().flatten().copied().collect();
+    let bbox = BoundingBox::of(&all_coords).context("No outer rings")?;
+
+    Ok(Region::Polygon { bbox, outers, holes })
+}
+
+#[cfg(test)]
+mod clip_tests {
+    use super::*;
+
+    #[test]
+    fn poly_region() {
+        let region = parse_poly(
+            "city
+first_area
+    0.0E+00  0.0E+00
+    1.0  0.0
+    1.0  1.0
+    0.0  1.0
+END
+!hole
+    0.25 0.25
+    0.75 0.25
+    0.75 0.75
+    0.25 0.75
+END
+END
+",
+        )
+        .unwrap();
+
+        assert!(region.contains((100_000_000, 100_000_000)));
+        assert!(!region.contains((500_000_000, 500_000_000)));
+        assert!(!region.contains((1_500_000_000, 500_000_000)));
+    }
+
+    #[test]
+    fn invalid_poly() {
+        assert!(parse_poly("city\n1\n0 0\n1 0\nEND\nEND").is_err());
+        assert!(parse_poly("city\n1\n0 0\n1 0\n1 1\nEND").is_err());
+        assert!(parse_poly("city\n1\n0 0\n1 x\n1 1\nEND\nEND").is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Context;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use std::collections::HashSet;
+use super::filter::{ElementType, KeyPatterns, TagFilter};
+
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
 #[derive(Default, Serialize, Deserialize)]
@@ -13,6 +16,175 @@ pub struct TableConfig {
     pub create_index_on: Vec<String>,
 }
 
+/// Configuration of a tag table, which may override the global tag key patterns for its element type.
+#[derive(Default, Serialize, Deserialize)]
+pub struct TagTableConfig {
+    #[serde(flatten)]
+    pub table: TableConfig,
+
+    pub skip_tag_keys: Option<KeyPatterns>,
+
+    pub keep_tag_keys: Option<KeyPatterns>,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipStrategy {
+    #[default]
+    Simple,
+    CompleteWays,
+}
+
+/// What to do with blocks which can't be read or parsed during the import.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Abort the import and roll it back.
+    Fail,
+    /// Print the error and continue.
+    #[default]
+    Skip,
+    /// Record the error in the `import_errors` table and continue.
+    Record,
+}
+
+/// What to do with the data imported so far when the import is interrupted by SIGINT or SIGTERM.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterruptPolicy {
+    /// Commit the blobs imported so far, which can be resumed if `commit_every` is set.
+    #[default]
+    Commit,
+    /// Roll back to the last commit.
+    Rollback,
+}
+
+/// Named set of SQLite pragmas used during the import.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SqlitePreset {
+    /// No syncing and an in-memory journal. A crash during the import leaves a corrupt database.
+    #[default]
+    Fast,
+    /// Full syncing and a rollback journal.
+    Safe,
+    /// Write-ahead log with normal syncing.
+    Wal,
+}
+
+impl SqlitePreset {
+    pub fn pragmas(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            SqlitePreset::Fast => &[("synchronous", "OFF"), ("journal_mode", "MEMORY")],
+            SqlitePreset::Safe => &[("synchronous", "FULL"), ("journal_mode", "DELETE")],
+            SqlitePreset::Wal => &[("synchronous", "NORMAL"), ("journal_mode", "WAL")],
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PragmaValue {
+    Bool(bool),
+    Integer(i64),
+    Text(String),
+}
+
+/// Pragmas which take a value that can be read back, so it can be restored after the import. Other pragmas run an
+/// action (like `optimize` or `wal_checkpoint`) or query the schema, and are rejected.
+const SETTABLE_PRAGMAS: &[&str] = &[
+    "analysis_limit",
+    "auto_vacuum",
+    "automatic_index",
+    "busy_timeout",
+    "cache_size",
+    "cache_spill",
+    "cell_size_check",
+    "checkpoint_fullfsync",
+    "defer_foreign_keys",
+    "foreign_keys",
+    "fullfsync",
+    "hard_heap_limit",
+    "ignore_check_constraints",
+    "journal_mode",
+    "journal_size_limit",
+    "legacy_alter_table",
+    "locking_mode",
+    "max_page_count",
+    "mmap_size",
+    "page_size",
+    "query_only",
+    "read_uncommitted",
+    "recursive_triggers",
+    "reverse_unordered_selects",
+    "secure_delete",
+    "soft_heap_limit",
+    "synchronous",
+    "temp_store",
+    "threads",
+    "trusted_schema",
+    "wal_autocheckpoint",
+];
+
+fn deserialize_pragmas<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, PragmaValue>, D::Error> {
+    let pragmas = BTreeMap::<String, PragmaValue>::deserialize(deserializer)?;
+
+    if let Some(name) = pragmas.keys().find(|name| !SETTABLE_PRAGMAS.contains(&name.as_str())) {
+        return Err(serde::de::Error::custom(format!(
+            "Unknown SQLite pragma `{name}`, or one which doesn't take a value"
+        )));
+    }
+
+    Ok(pragmas)
+}
+
+/// SQLite settings of the import: a preset, and pragmas like `cache_size` or `mmap_size` overriding it.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SqliteConfig {
+    #[serde(default)]
+    pub preset: SqlitePreset,
+
+    #[serde(flatten, deserialize_with = "deserialize_pragmas")]
+    pub pragmas: BTreeMap<String, PragmaValue>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipConfig {
+    /// Bounding box as `[left, bottom, right, top]` in degrees.
+    pub bbox: Option<[f64; 4]>,
+
+    /// Path of an Osmosis polygon filter file.
+    pub poly: Option<PathBuf>,
+
+    #[serde(default)]
+    pub strategy: ClipStrategy,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FilterConfig {
+    pub expressions: TagFilter,
+
+    #[serde(default = "default_true")]
+    pub add_dependencies: bool,
+}
+
+fn default_name_keys() -> KeyPatterns {
+    let keys = ["name", "name:*", "alt_name", "official_name", "addr:*"];
+    KeyPatterns::try_from(keys.map(str::to_string).to_vec()).expect("Default name keys should be valid")
+}
+
+/// Configuration of the `names_fts` full-text search table.
+#[derive(Serialize, Deserialize)]
+pub struct NamesFtsConfig {
+    /// Keys of the tags whose values are indexed.
+    #[serde(default = "default_name_keys")]
+    pub keys: KeyPatterns,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub input_pbf: PathBuf,
@@ -23,11 +195,71 @@ pub struct Config {
     pub overwrite_output: bool,
 
     #[serde(default)]
-    pub skip_tag_keys: HashSet<String>,
+    pub skip_tag_keys: KeyPatterns,
+
+    #[serde(default)]
+    pub keep_tag_keys: KeyPatterns,
+
+    #[serde(default = "default_worker_threads")]
+    pub worker_threads: usize,
+
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+
+    #[serde(default)]
+    pub on_interrupt: InterruptPolicy,
+
+    #[serde(default)]
+    pub sqlite: SqliteConfig,
+
+    /// Number of blobs after which the import is committed, along with its progress, so it can be resumed.
+    pub commit_every: Option<NonZeroUsize>,
+
+    /// Whether `ANALYZE` is run after the import, gathering statistics for the query planner.
+    #[serde(default)]
+    pub analyze: bool,
+
+    /// Whether `PRAGMA optimize` is run after the import.
+    #[serde(default)]
+    pub optimize: bool,
+
+    #[serde(default)]
+    pub geopackage: bool,
+
+    /// Whether the input is a full-history file, in which case all versions of elements are imported.
+    #[serde(default)]
+    pub history: bool,
+
+    /// UTC timestamp like `2024-01-31T12:00:00Z`. If given, the input must be a full-history file, and a regular dump
+    /// of the data at this time is created.
+    pub snapshot_at: Option<String>,
+
+    /// Whether user names are stored once per user in the `users` table, instead of in each row of the info tables.
+    #[serde(default)]
+    pub normalize_users: bool,
+
+    /// Whether tag keys and values are stored once in the `tag_keys` and `tag_values` tables, and referenced by their
+    /// IDs in the tag tables.
+    #[serde(default)]
+    pub normalize_tags: bool,
+
+    /// Whether tags are stored as JSON objects in a `tags` column of the element tables, instead of in the tag tables.
+    #[serde(default)]
+    pub json_tags: bool,
+
+    pub clip: Option<ClipConfig>,
+
+    pub filter: Option<FilterConfig>,
 
     #[serde(default)]
     pub header: TableConfig,
 
+    #[serde(default)]
+    pub replication_state: TableConfig,
+
+    #[serde(default)]
+    pub import_metadata: TableConfig,
+
     #[serde(default)]
     pub nodes: TableConfig,
 
@@ -35,7 +267,7 @@ pub struct Config {
     pub node_info: TableConfig,
 
     #[serde(default)]
-    pub node_tags: TableConfig,
+    pub node_tags: TagTableConfig,
 
     #[serde(default)]
     pub relations: TableConfig,
@@ -47,7 +279,7 @@ pub struct Config {
     pub relation_members: TableConfig,
 
     #[serde(default)]
-    pub relation_tags: TableConfig,
+    pub relation_tags: TagTableConfig,
 
     #[serde(default)]
     pub ways: TableConfig,
@@ -59,7 +291,46 @@ pub struct Config {
     pub way_refs: TableConfig,
 
     #[serde(default)]
-    pub way_tags: TableConfig,
+    pub way_tags: TagTableConfig,
+
+    pub way_geometries: Option<TableConfig>,
+
+    pub nodes_rtree: Option<TableConfig>,
+
+    pub ways_rtree: Option<TableConfig>,
+
+    pub areas: Option<TableConfig>,
+
+    pub changesets: Option<TableConfig>,
+
+    pub names_fts: Option<NamesFtsConfig>,
+}
+
+impl Config {
+    /// Returns whether tags with `key` are skipped for elements of `element_type`.
+    ///
+    /// Keys matching `skip_tag_keys` are skipped, unless they match `keep_tag_keys` too. If only `keep_tag_keys` is
+    /// given, all other keys are skipped. The tag tables' own patterns replace the global ones.
+    pub fn skips_tag_key(&self, element_type: ElementType, key: &str) -> bool {
+        let tag_table = match element_type {
+            ElementType::Node => &self.node_tags,
+            ElementType::Way => &self.way_tags,
+            ElementType::Relation => &self.relation_tags,
+        };
+
+        let skip = tag_table.skip_tag_keys.as_ref().unwrap_or(&self.skip_tag_keys);
+        let keep = tag_table.keep_tag_keys.as_ref().unwrap_or(&self.keep_tag_keys);
+
+        if skip.is_empty() {
+            !keep.is_empty() && !keep.matches(key)
+        } else {
+            skip.matches(key) && !keep.matches(key)
+        }
+    }
+}
+
+fn default_worker_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
 }
 
 pub fn read_config(config_path: &str) -> anyhow::Result<Config> {
@@ -85,6 +356,7 @@ mod config_tests {
 input_pbf = "osm.pbf"
 output_db = "out.db"
 overwrite_output = true
+worker_threads = 3
 
 [node_tags]
 create_index_on = ["node_id, key"]
@@ -95,6 +367,84 @@ create_index_on = ["node_id, key"]
         assert_eq!(config.input_pbf.to_str().unwrap(), "osm.pbf");
         assert_eq!(config.output_db.to_str().unwrap(), "out.db");
         assert!(config.overwrite_output);
-        assert_eq!(config.node_tags.create_index_on, vec!["node_id, key"]);
+        assert_eq!(config.worker_threads, 3);
+        assert_eq!(config.node_tags.table.create_index_on, vec!["node_id, key"]);
+    }
+
+    #[test]
+    fn tag_key_patterns() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+skip_tag_keys = ["tiger:*", "name:*"]
+keep_tag_keys = ["name:en"]
+
+[relation_tags]
+keep_tag_keys = ["type", "name"]
+"#,
+        )
+        .unwrap();
+
+        assert!(config.skips_tag_key(ElementType::Way, "tiger:cfcc"));
+        assert!(config.skips_tag_key(ElementType::Way, "name:de"));
+        assert!(!config.skips_tag_key(ElementType::Way, "name:en"));
+        assert!(!config.skips_tag_key(ElementType::Node, "name"));
+        assert!(config.skips_tag_key(ElementType::Relation, "tiger:cfcc"));
+        assert!(!config.skips_tag_key(ElementType::Relation, "type"));
+        assert!(config.skips_tag_key(ElementType::Relation, "name:en"));
+    }
+
+    #[test]
+    fn sqlite_pragmas() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+
+[sqlite]
+preset = "wal"
+cache_size = -200000
+temp_store = "MEMORY"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.sqlite.preset, SqlitePreset::Wal);
+        assert_eq!(
+            config.sqlite.pragmas.into_iter().collect::<Vec<_>>(),
+            [
+                ("cache_size".to_string(), PragmaValue::Integer(-200000)),
+                ("temp_store".to_string(), PragmaValue::Text("MEMORY".to_string()))
+            ]
+        );
+
+        for pragma in ["cache_sise = -1000", "optimize = true", "wal_checkpoint = \"TRUNCATE\""] {
+            let error = toml::from_str::<Config>(&format!(
+                "input_pbf = \"osm.pbf\"\noutput_db = \"out.db\"\n[sqlite]\n{pragma}"
+            ))
+            .err()
+            .unwrap();
+            assert!(error.to_string().contains("Unknown SQLite pragma"));
+        }
+    }
+
+    #[test]
+    fn name_keys() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+
+[names_fts]
+"#,
+        )
+        .unwrap();
+
+        let keys = &config.names_fts.unwrap().keys;
+        assert!(keys.matches("name"));
+        assert!(keys.matches("name:hu"));
+        assert!(keys.matches("addr:street"));
+        assert!(!keys.matches("highway"));
     }
 }
@@ -1,23 +1,258 @@
-use rusqlite::Transaction;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
 
-use super::config::{Config, TableConfig};
+use super::config::{Config, ErrorPolicy, PragmaValue, SqliteConfig};
+use super::geometry::{BoundingBox, GEOPACKAGE_SRS_ID};
+use super::progress;
+
+const WGS_84_DEFINITION: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AXIS["Latitude",NORTH],AXIS["Longitude",EAST],AUTHORITY["EPSG","4326"]]"#;
+
+/// Applies the pragmas of the preset and the configured ones, returning the previous values of the changed pragmas, to be
+/// restored by `restore_pragmas` after the import. Only pragmas taking a value are accepted by the configuration.
+pub fn apply_pragmas(conn: &Connection, config: &SqliteConfig) -> rusqlite::Result<Vec<(String, Value)>> {
+    let mut pragmas: Vec<(String, PragmaValue)> = config
+        .preset
+        .pragmas()
+        .iter()
+        .filter(|(name, _)| !config.pragmas.contains_key(*name))
+        .map(|(name, value)| (name.to_string(), PragmaValue::Text(value.to_string())))
+        .chain(config.pragmas.clone())
+        .collect();
+
+    // The page size can't be changed once the journal mode is set to WAL
+    pragmas.sort_by_key(|(name, _)| name != "page_size");
+
+    let mut previous_values = Vec::new();
+
+    for (name, value) in pragmas {
+        if let Some(previous_value) = conn.pragma_query_value(None, &name, |row| row.get(0)).optional()? {
+            previous_values.push((name.clone(), previous_value));
+        }
+
+        match value {
+            PragmaValue::Bool(value) => conn.pragma_update(None, &name, value)?,
+            PragmaValue::Integer(value) => conn.pragma_update(None, &name, value)?,
+            PragmaValue::Text(value) => conn.pragma_update(None, &name, value)?,
+        }
+    }
+
+    Ok(previous_values)
+}
+
+pub fn restore_pragmas(conn: &Connection, previous_values: &[(String, Value)]) -> rusqlite::Result<()> {
+    for (name, value) in previous_values.iter().rev() {
+        conn.pragma_update(None, name, value)?;
+    }
+    Ok(())
+}
+
+/// Creates the GeoPackage metadata tables and feature tables for nodes, ways and (if enabled) areas.
+fn create_geopackage_tables(tr: &Transaction, config: &Config) -> rusqlite::Result<()> {
+    tr.pragma_update(None, "application_id", 0x47504B47)?; // "GPKG"
+    tr.pragma_update(None, "user_version", 10400)?; // Version 1.4.0
+
+    tr.execute(
+        "CREATE TABLE gpkg_spatial_ref_sys (
+            srs_name TEXT NOT NULL,
+            srs_id INTEGER PRIMARY KEY,
+            organization TEXT NOT NULL,
+            organization_coordsys_id INTEGER NOT NULL,
+            definition TEXT NOT NULL,
+            description TEXT
+        )",
+        [],
+    )?;
+
+    let mut insert_srs = tr.prepare(
+        "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition, description) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    insert_srs.execute(params![
+        "WGS 84 geodetic",
+        GEOPACKAGE_SRS_ID,
+        "EPSG",
+        4326,
+        WGS_84_DEFINITION,
+        "longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid"
+    ])?;
+    insert_srs.execute(params![
+        "Undefined cartesian SRS",
+        -1,
+        "NONE",
+        -1,
+        "undefined",
+        "undefined cartesian coordinate reference system"
+    ])?;
+    insert_srs.execute(params![
+        "Undefined geographic SRS",
+        0,
+        "NONE",
+        0,
+        "undefined",
+        "undefined geographic coordinate reference system"
+    ])?;
+
+    tr.execute(
+        "CREATE TABLE gpkg_contents (
+            table_name TEXT NOT NULL PRIMARY KEY,
+            data_type TEXT NOT NULL,
+            identifier TEXT UNIQUE,
+            description TEXT DEFAULT '',
+            last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+            min_x DOUBLE,
+            min_y DOUBLE,
+            max_x DOUBLE,
+            max_y DOUBLE,
+            srs_id INTEGER,
+            FOREIGN KEY(srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+        )",
+        [],
+    )?;
+
+    tr.execute(
+        "CREATE TABLE gpkg_geometry_columns (
+            table_name TEXT NOT NULL,
+            column_name TEXT NOT NULL,
+            geometry_type_name TEXT NOT NULL,
+            srs_id INTEGER NOT NULL,
+            z TINYINT NOT NULL,
+            m TINYINT NOT NULL,
+            PRIMARY KEY(table_name, column_name),
+            UNIQUE(table_name),
+            FOREIGN KEY(table_name) REFERENCES gpkg_contents(table_name),
+            FOREIGN KEY(srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+        )",
+        [],
+    )?;
+
+    let mut feature_tables = vec![
+        (
+            "node_features",
+            "POINT",
+            "fid INTEGER PRIMARY KEY AUTOINCREMENT, geom POINT NOT NULL",
+        ),
+        (
+            "way_features",
+            "LINESTRING",
+            "fid INTEGER PRIMARY KEY AUTOINCREMENT, geom LINESTRING NOT NULL",
+        ),
+    ];
+
+    if config.areas.as_ref().is_some_and(|table| !table.skip) {
+        feature_tables.push((
+            "area_features",
+            "MULTIPOLYGON",
+            "fid INTEGER PRIMARY KEY AUTOINCREMENT, way_id INTEGER, relation_id INTEGER, geom MULTIPOLYGON NOT NULL",
+        ));
+    }
+
+    for (table, geometry_type, columns) in feature_tables {
+        tr.execute(&format!("CREATE TABLE {table} ({columns})"), [])?;
+
+        tr.execute(
+            "INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES (?1, 'features', ?1, ?2)",
+            params![table, GEOPACKAGE_SRS_ID],
+        )?;
+
+        tr.execute(
+            "INSERT INTO gpkg_geometry_columns (table_name, column_name, geometry_type_name, srs_id, z, m) VALUES (?1, 'geom', ?2, ?3, 0, 0)",
+            params![table, geometry_type, GEOPACKAGE_SRS_ID],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Sets the extents of GeoPackage feature tables in `gpkg_contents` to the bounding boxes of their features.
+pub fn update_geopackage_extents(conn: &Connection, extents: &[(&str, Option<BoundingBox>)]) -> rusqlite::Result<()> {
+    let mut update_extent =
+        conn.prepare("UPDATE gpkg_contents SET min_x = ?2, min_y = ?3, max_x = ?4, max_y = ?5 WHERE table_name = ?1")?;
+
+    for (table, extent) in extents {
+        if let Some(extent) = extent {
+            update_extent.execute(params![
+                table,
+                extent.min_lon as f64 / 1e9,
+                extent.min_lat as f64 / 1e9,
+                extent.max_lon as f64 / 1e9,
+                extent.max_lat as f64 / 1e9
+            ])?;
+        }
+    }
+
+    Ok(())
+}
 
 pub fn create_tables(tr: &Transaction, config: &Config) -> rusqlite::Result<()> {
-    let create_index = |config: &TableConfig, table: &str| -> rusqlite::Result<()> {
-        for columns in &config.create_index_on {
-            let columns_split: Vec<&str> = columns.split(',').map(str::trim).collect();
-            tr.execute(
-                &format!(
-                    "CREATE INDEX {}_{} ON {} ({})",
-                    table,
-                    columns_split.join("_"),
-                    table,
-                    columns_split.join(", ")
-                ),
-                [],
-            )?;
+    let tags_column = if config.json_tags { ", tags TEXT" } else { "" };
+
+    // In history mode, elements are keyed by their IDs and versions, and rows belonging to an element reference a
+    // single version of it
+    let (element_key, element_primary_key) = if config.history {
+        ("id INTEGER, version INTEGER", ", PRIMARY KEY(id, version)")
+    } else {
+        ("id INTEGER PRIMARY KEY", "")
+    };
+
+    let element_key_columns = |column: &str| {
+        if config.history {
+            format!("{column}, version")
+        } else {
+            column.to_string()
+        }
+    };
+
+    let element_ref = |column: &str| {
+        if config.history {
+            format!("{column} INTEGER, version INTEGER")
+        } else {
+            format!("{column} INTEGER")
         }
-        Ok(())
+    };
+
+    let foreign_key = |column: &str, table: &str| {
+        format!(
+            "FOREIGN KEY({}) REFERENCES {table}({})",
+            element_key_columns(column),
+            element_key_columns("id")
+        )
+    };
+
+    // Normalized info tables only reference users by their IDs
+    let user_column = if config.normalize_users { "" } else { "user TEXT," };
+
+    if config.normalize_users {
+        tr.execute(
+            "CREATE TABLE users (
+                user_id INTEGER PRIMARY KEY,
+                user TEXT
+            )",
+            [],
+        )?;
+    }
+
+    // Normalized tag tables reference their keys and values in the tag dictionary
+    let tag_columns = if config.normalize_tags {
+        tr.execute(
+            "CREATE TABLE tag_keys (
+                id INTEGER PRIMARY KEY,
+                key TEXT NOT NULL UNIQUE
+            )",
+            [],
+        )?;
+
+        tr.execute(
+            "CREATE TABLE tag_values (
+                id INTEGER PRIMARY KEY,
+                value TEXT NOT NULL UNIQUE
+            )",
+            [],
+        )?;
+
+        "key_id INTEGER REFERENCES tag_keys(id),
+         value_id INTEGER REFERENCES tag_values(id),"
+    } else {
+        "key TEXT,
+         value TEXT,"
     };
 
     if !config.header.skip {
@@ -28,167 +263,628 @@ pub fn create_tables(tr: &Transaction, config: &Config) -> rusqlite::Result<()>
             )",
             [],
         )?;
+    }
+
+    if config.on_error == ErrorPolicy::Record {
+        tr.execute(
+            "CREATE TABLE import_errors (
+                blob_index INTEGER,
+                blob_offset INTEGER,
+                error TEXT
+            )",
+            [],
+        )?;
+    }
 
-        create_index(&config.header, "header")?;
+    if config.commit_every.is_some() {
+        progress::create_table(tr)?;
     }
 
-    if !config.nodes.skip {
+    if !config.import_metadata.skip {
+        progress::create_metadata_table(tr)?;
+    }
+
+    if !config.replication_state.skip {
         tr.execute(
-            "CREATE TABLE nodes (
-                id INTEGER PRIMARY KEY,
-                lat INTEGER NOT NULL,
-                lon INTEGER NOT NULL
+            "CREATE TABLE replication_state (
+                sequence_number INTEGER NOT NULL,
+                timestamp INTEGER,
+                base_url TEXT
             )",
             [],
         )?;
+    }
+
+    if !config.nodes.skip {
+        // Deleted versions of nodes have no coordinates
+        let coord_constraint = if config.history { "" } else { " NOT NULL" };
 
-        create_index(&config.nodes, "nodes")?;
+        tr.execute(
+            &format!(
+                "CREATE TABLE nodes (
+                    {element_key},
+                    lat INTEGER{coord_constraint},
+                    lon INTEGER{coord_constraint}{tags_column}{element_primary_key}
+                )"
+            ),
+            [],
+        )?;
 
-        if !config.node_tags.skip {
+        if config.nodes_rtree.as_ref().is_some_and(|table| !table.skip) {
             tr.execute(
-                "CREATE TABLE node_tags (
-                    node_id INTEGER,
-                    key TEXT,
-                    value TEXT,
-                    FOREIGN KEY(node_id) REFERENCES nodes(id)
+                "CREATE VIRTUAL TABLE nodes_rtree USING rtree(
+                    id,
+                    min_lat, max_lat,
+                    min_lon, max_lon
                 )",
                 [],
             )?;
+        }
 
-            create_index(&config.node_tags, "node_tags")?;
+        if !config.json_tags && !config.node_tags.table.skip {
+            tr.execute(
+                &format!(
+                    "CREATE TABLE node_tags (
+                        {},
+                        {tag_columns}
+                        {}
+                    )",
+                    element_ref("node_id"),
+                    foreign_key("node_id", "nodes")
+                ),
+                [],
+            )?;
         }
 
         if !config.node_info.skip {
             tr.execute(
-                "CREATE TABLE node_info (
-                    node_id INTEGER,
-                    version INTEGER,
-                    timestamp INTEGER,
-                    user_id INTEGER,
-                    user TEXT,
-                    visible BOOL,
-                    FOREIGN KEY(node_id) REFERENCES nodes(id)
-                )",
+                &format!(
+                    "CREATE TABLE node_info (
+                        node_id INTEGER,
+                        version INTEGER,
+                        timestamp INTEGER,
+                        changeset INTEGER,
+                        user_id INTEGER,
+                        {user_column}
+                        visible BOOL,
+                        {}
+                    )",
+                    foreign_key("node_id", "nodes")
+                ),
                 [],
             )?;
-
-            create_index(&config.node_info, "node_info")?;
         }
     }
 
     if !config.ways.skip {
         tr.execute(
-            "CREATE TABLE ways (
-                id INTEGER PRIMARY KEY
-            )",
+            &format!(
+                "CREATE TABLE ways (
+                    {element_key}{tags_column}{element_primary_key}
+                )"
+            ),
             [],
         )?;
 
-        create_index(&config.ways, "ways")?;
+        if !config.json_tags && !config.way_tags.table.skip {
+            tr.execute(
+                &format!(
+                    "CREATE TABLE way_tags (
+                        {},
+                        {tag_columns}
+                        {}
+                    )",
+                    element_ref("way_id"),
+                    foreign_key("way_id", "ways")
+                ),
+                [],
+            )?;
+        }
 
-        if !config.way_tags.skip {
+        if !config.way_info.skip {
             tr.execute(
-                "CREATE TABLE way_tags (
-                    way_id INTEGER,
-                    key TEXT,
-                    value TEXT,
-                    FOREIGN KEY(way_id) REFERENCES ways(id)
-                )",
+                &format!(
+                    "CREATE TABLE way_info (
+                        way_id INTEGER,
+                        version INTEGER,
+                        timestamp INTEGER,
+                        changeset INTEGER,
+                        user_id INTEGER,
+                        {user_column}
+                        visible BOOL,
+                        {}
+                    )",
+                    foreign_key("way_id", "ways")
+                ),
                 [],
             )?;
+        }
+
+        if !config.way_refs.skip {
+            // Referenced nodes can't have foreign keys in history mode, as the referenced version isn't known
+            let ref_node_foreign_key = if config.history {
+                ""
+            } else {
+                ", FOREIGN KEY(ref_node_id) REFERENCES nodes(id) DEFERRABLE INITIALLY DEFERRED"
+            };
 
-            create_index(&config.way_tags, "way_tags")?;
+            tr.execute(
+                &format!(
+                    "CREATE TABLE way_refs (
+                        {},
+                        sequence_id INTEGER,
+                        ref_node_id INTEGER,
+                        PRIMARY KEY({}, sequence_id),
+                        {}{ref_node_foreign_key}
+                    )",
+                    element_ref("way_id"),
+                    element_key_columns("way_id"),
+                    foreign_key("way_id", "ways")
+                ),
+                [],
+            )?;
         }
 
-        if !config.way_info.skip {
+        if config.way_geometries.as_ref().is_some_and(|table| !table.skip) {
             tr.execute(
-                "CREATE TABLE way_info (
-                    way_id INTEGER,
-                    version INTEGER,
-                    timestamp INTEGER,
-                    user_id INTEGER,
-                    user TEXT,
-                    visible BOOL,
+                "CREATE TABLE way_geometries (
+                    way_id INTEGER PRIMARY KEY,
+                    geometry BLOB NOT NULL,
+                    min_lat INTEGER NOT NULL,
+                    min_lon INTEGER NOT NULL,
+                    max_lat INTEGER NOT NULL,
+                    max_lon INTEGER NOT NULL,
                     FOREIGN KEY(way_id) REFERENCES ways(id)
                 )",
                 [],
             )?;
-
-            create_index(&config.way_info, "way_info")?;
         }
 
-        if !config.way_refs.skip {
+        if config.ways_rtree.as_ref().is_some_and(|table| !table.skip) {
             tr.execute(
-                "CREATE TABLE way_refs (
-                    way_id INTEGER,
-                    ref_node_id INTEGER,
-                    FOREIGN KEY(way_id) REFERENCES ways(id),
-                    FOREIGN KEY(ref_node_id) REFERENCES nodes(id) DEFERRABLE INITIALLY DEFERRED
+                "CREATE VIRTUAL TABLE ways_rtree USING rtree(
+                    id,
+                    min_lat, max_lat,
+                    min_lon, max_lon
                 )",
                 [],
             )?;
-
-            create_index(&config.way_refs, "way_refs")?;
         }
     }
 
     if !config.relations.skip {
         tr.execute(
-            "CREATE TABLE relations (
-                id INTEGER PRIMARY KEY
-            )",
+            &format!(
+                "CREATE TABLE relations (
+                    {element_key}{tags_column}{element_primary_key}
+                )"
+            ),
             [],
         )?;
 
-        create_index(&config.relations, "relations")?;
-
         if !config.relation_members.skip {
             tr.execute(
-                "CREATE TABLE relation_members (
-                    relation_id INTEGER,
-                    member_node_id INTEGER,
-                    member_way_id INTEGER,
-                    member_relation_id INTEGER,
-                    role TEXT,
-                    FOREIGN KEY(relation_id) REFERENCES relations(id)
-                )",
+                &format!(
+                    "CREATE TABLE relation_members (
+                        {},
+                        member_node_id INTEGER,
+                        member_way_id INTEGER,
+                        member_relation_id INTEGER,
+                        role TEXT,
+                        sequence_id INTEGER,
+                        PRIMARY KEY({}, sequence_id),
+                        {}
+                    )",
+                    element_ref("relation_id"),
+                    element_key_columns("relation_id"),
+                    foreign_key("relation_id", "relations")
+                ),
                 [],
             )?;
-
-            create_index(&config.relation_members, "relation_members")?;
         }
 
-        if !config.relation_tags.skip {
+        if !config.json_tags && !config.relation_tags.table.skip {
             tr.execute(
-                "CREATE TABLE relation_tags (
-                    relation_id INTEGER,
-                    key TEXT,
-                    value TEXT,
-                    FOREIGN KEY(relation_id) REFERENCES relations(id)
-                )",
+                &format!(
+                    "CREATE TABLE relation_tags (
+                        {},
+                        {tag_columns}
+                        {}
+                    )",
+                    element_ref("relation_id"),
+                    foreign_key("relation_id", "relations")
+                ),
                 [],
             )?;
-
-            create_index(&config.relation_tags, "relation_tags")?;
         }
 
         if !config.relation_info.skip {
             tr.execute(
-                "CREATE TABLE relation_info (
-                    relation_id INTEGER,
-                    version INTEGER,
-                    timestamp INTEGER,
-                    user_id INTEGER,
-                    user TEXT,
-                    visible BOOL,
-                    FOREIGN KEY(relation_id) REFERENCES relations(id)
-                )",
+                &format!(
+                    "CREATE TABLE relation_info (
+                        relation_id INTEGER,
+                        version INTEGER,
+                        timestamp INTEGER,
+                        changeset INTEGER,
+                        user_id INTEGER,
+                        {user_column}
+                        visible BOOL,
+                        {}
+                    )",
+                    foreign_key("relation_id", "relations")
+                ),
                 [],
             )?;
+        }
+    }
 
-            create_index(&config.relation_info, "relation_info")?;
+    if config.areas.as_ref().is_some_and(|table| !table.skip) {
+        tr.execute(
+            "CREATE TABLE areas (
+                way_id INTEGER,
+                relation_id INTEGER,
+                geometry BLOB NOT NULL,
+                min_lat INTEGER NOT NULL,
+                min_lon INTEGER NOT NULL,
+                max_lat INTEGER NOT NULL,
+                max_lon INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        tr.execute(
+            "CREATE TABLE area_errors (
+                way_id INTEGER,
+                relation_id INTEGER,
+                error TEXT
+            )",
+            [],
+        )?;
+    }
+
+    if config.changesets.as_ref().is_some_and(|table| !table.skip) {
+        tr.execute(
+            "CREATE TABLE changesets (
+                id INTEGER PRIMARY KEY,
+                user_id INTEGER,
+                user TEXT,
+                node_count INTEGER NOT NULL,
+                way_count INTEGER NOT NULL,
+                relation_count INTEGER NOT NULL,
+                min_lat INTEGER,
+                min_lon INTEGER,
+                max_lat INTEGER,
+                max_lon INTEGER
+            )",
+            [],
+        )?;
+    }
+
+    if config.names_fts.is_some() {
+        tr.execute(
+            "CREATE VIRTUAL TABLE names_fts USING fts5(
+                element_type UNINDEXED,
+                element_id UNINDEXED,
+                key UNINDEXED,
+                value,
+                prefix = '2 3'
+            )",
+            [],
+        )?;
+    }
+
+    if config.geopackage {
+        create_geopackage_tables(tr, config)?;
+    }
+
+    if config.normalize_users {
+        create_info_views(tr, config)?;
+    }
+
+    if config.normalize_tags {
+        create_tag_views(tr, config)?;
+    }
+
+    Ok(())
+}
+
+/// Creates the configured indices after the data has been loaded, as building them at once is faster than updating
+/// them on each insert. Tables which don't exist (like skipped ones) and existing indices (when resuming) are left out.
+pub fn create_indices(conn: &Connection, config: &Config) -> rusqlite::Result<()> {
+    let mut tables = vec![
+        ("header", &config.header),
+        ("nodes", &config.nodes),
+        ("node_tags", &config.node_tags.table),
+        ("node_info", &config.node_info),
+        ("ways", &config.ways),
+        ("way_tags", &config.way_tags.table),
+        ("way_info", &config.way_info),
+        ("way_refs", &config.way_refs),
+        ("relations", &config.relations),
+        ("relation_members", &config.relation_members),
+        ("relation_tags", &config.relation_tags.table),
+        ("relation_info", &config.relation_info),
+    ];
+
+    for (table, table_config) in [
+        ("way_geometries", &config.way_geometries),
+        ("areas", &config.areas),
+        ("changesets", &config.changesets),
+    ] {
+        if let Some(table_config) = table_config {
+            tables.push((table, table_config));
+        }
+    }
+
+    let mut indices = Vec::new();
+
+    for (table, table_config) in tables {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            [table],
+            |row| row.get(0),
+        )?;
+
+        if !exists {
+            continue;
         }
+
+        for columns in &table_config.create_index_on {
+            let columns_split: Vec<&str> = columns.split(',').map(str::trim).collect();
+            let index = format!("{}_{}", table, columns_split.join("_"));
+            let sql = format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+                index,
+                table,
+                columns_split.join(", ")
+            );
+            indices.push((index, sql));
+        }
+    }
+
+    for (i, (index, sql)) in indices.iter().enumerate() {
+        println!("Creating index {index} ({}/{})", i + 1, indices.len());
+        conn.execute(sql, [])?;
+    }
+
+    if config.analyze {
+        println!("Analyzing tables");
+        conn.execute_batch("ANALYZE")?;
+    }
+
+    if config.optimize {
+        println!("Optimizing database");
+        conn.execute_batch("PRAGMA optimize")?;
+    }
+
+    Ok(())
+}
+
+/// Creates views of the normalized info tables with the user names joined from the `users` table, in the shape of the
+/// regular info tables.
+fn create_info_views(tr: &Transaction, config: &Config) -> rusqlite::Result<()> {
+    let info_tables = [
+        ("node_info", "node_id", config.nodes.skip || config.node_info.skip),
+        ("way_info", "way_id", config.ways.skip || config.way_info.skip),
+        (
+            "relation_info",
+            "relation_id",
+            config.relations.skip || config.relation_info.skip,
+        ),
+    ];
+
+    for (table, id_column, skip) in info_tables {
+        if skip {
+            continue;
+        }
+
+        tr.execute(
+            &format!(
+                "CREATE VIEW {table}_with_users AS
+                    SELECT i.{id_column}, i.version, i.timestamp, i.changeset, i.user_id, u.user, i.visible
+                    FROM {table} i LEFT JOIN users u ON u.user_id = i.user_id"
+            ),
+            [],
+        )?;
     }
 
     Ok(())
 }
+
+/// Creates views of the normalized tag tables with the keys and values joined from the tag dictionary, in the shape of
+/// the regular tag tables.
+fn create_tag_views(tr: &Transaction, config: &Config) -> rusqlite::Result<()> {
+    let tag_tables = [
+        ("node_tags", "node_id", config.nodes.skip || config.node_tags.table.skip),
+        ("way_tags", "way_id", config.ways.skip || config.way_tags.table.skip),
+        (
+            "relation_tags",
+            "relation_id",
+            config.relations.skip || config.relation_tags.table.skip,
+        ),
+    ];
+
+    let version = if config.history { " t.version," } else { "" };
+
+    for (table, id_column, skip) in tag_tables {
+        if skip {
+            continue;
+        }
+
+        tr.execute(
+            &format!(
+                "CREATE VIEW {table}_with_strings AS
+                    SELECT t.{id_column},{version} k.key, v.value
+                    FROM {table} t JOIN tag_keys k ON k.id = t.key_id JOIN tag_values v ON v.id = t.value_id"
+            ),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod db_tests {
+    use super::*;
+
+    fn create(config: &str) -> rusqlite::Connection {
+        let config: Config =
+            toml::from_str(&format!("input_pbf = \"osm.pbf\"\noutput_db = \"out.db\"\n{config}")).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tables(&tr, &config).unwrap();
+        tr.commit().unwrap();
+        conn
+    }
+
+    #[test]
+    fn history_tables() {
+        let conn = create("history = true");
+
+        conn.execute_batch(
+            "INSERT INTO nodes (id, version, lat, lon) VALUES (1, 1, 0, 0), (1, 2, NULL, NULL);
+             INSERT INTO ways (id, version) VALUES (10, 1), (10, 2);
+             INSERT INTO way_refs (way_id, version, sequence_id, ref_node_id) VALUES (10, 1, 0, 1), (10, 2, 0, 1);
+             INSERT INTO way_tags (way_id, version, key, value) VALUES (10, 2, 'highway', 'path');",
+        )
+        .unwrap();
+
+        assert!(
+            conn.execute("INSERT INTO ways (id, version) VALUES (10, 2)", [])
+                .is_err()
+        );
+        assert!(
+            conn.execute(
+                "INSERT INTO way_tags (way_id, version, key, value) VALUES (10, 3, 'a', 'b')",
+                []
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn normalized_users() {
+        let conn = create("normalize_users = true");
+
+        conn.execute_batch(
+            "INSERT INTO users (user_id, user) VALUES (7, 'A');
+             INSERT INTO nodes (id, lat, lon) VALUES (1, 0, 0), (2, 0, 0);
+             INSERT INTO node_info (node_id, version, timestamp, changeset, user_id, visible) VALUES (1, 1, 0, 3, 7, 1), (2, 1, 0, 3, NULL, 1);",
+        )
+        .unwrap();
+
+        let users: Vec<Option<String>> = conn
+            .prepare("SELECT user FROM node_info_with_users ORDER BY node_id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+
+        assert_eq!(users, [Some("A".to_string()), None]);
+        assert!(conn.prepare("SELECT user FROM node_info").is_err());
+    }
+
+    #[test]
+    fn names_search() {
+        let conn = create("[names_fts]");
+
+        conn.execute_batch(
+            "INSERT INTO names_fts (element_type, element_id, key, value) VALUES
+                ('node', 1, 'name', 'Budapest'), ('way', 2, 'addr:street', 'Budai út'), ('node', 3, 'name', 'Wien');",
+        )
+        .unwrap();
+
+        let ids: Vec<i64> = conn
+            .prepare("SELECT element_id FROM names_fts WHERE names_fts MATCH 'buda*' ORDER BY rank, element_id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+
+        assert_eq!(ids, [1, 2]);
+    }
+
+    #[test]
+    fn deferred_indices() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+json_tags = true
+analyze = true
+
+[nodes]
+create_index_on = ["lat, lon"]
+
+[node_tags]
+create_index_on = ["node_id, key"]
+"#,
+        )
+        .unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+        create_tables(&tr, &config).unwrap();
+        tr.commit().unwrap();
+
+        let indices = |conn: &Connection| -> Vec<String> {
+            conn.prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND name NOT LIKE 'sqlite_%'")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+
+        assert!(indices(&conn).is_empty());
+
+        create_indices(&conn, &config).unwrap();
+        assert_eq!(indices(&conn), ["nodes_lat_lon"]);
+    }
+
+    #[test]
+    fn pragmas() {
+        let config: Config = toml::from_str(
+            r#"
+input_pbf = "osm.pbf"
+output_db = "out.db"
+
+[sqlite]
+preset = "safe"
+cache_size = -1000
+"#,
+        )
+        .unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let pragma = |name: &str| -> i64 { conn.pragma_query_value(None, name, |row| row.get(0)).unwrap() };
+
+        let (synchronous, cache_size) = (pragma("synchronous"), pragma("cache_size"));
+
+        let previous_values = apply_pragmas(&conn, &config.sqlite).unwrap();
+        assert_eq!((pragma("synchronous"), pragma("cache_size")), (2, -1000));
+
+        restore_pragmas(&conn, &previous_values).unwrap();
+        assert_eq!((pragma("synchronous"), pragma("cache_size")), (synchronous, cache_size));
+    }
+
+    #[test]
+    fn regular_tables() {
+        let conn = create("");
+
+        conn.execute("INSERT INTO nodes (id, lat, lon) VALUES (1, 0, 0)", [])
+            .unwrap();
+        assert!(
+            conn.execute("INSERT INTO nodes (id, lat, lon) VALUES (1, 1, 1)", [])
+                .is_err()
+        );
+        assert!(
+            conn.execute("INSERT INTO nodes (id, lat, lon) VALUES (2, NULL, NULL)", [])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use rusqlite::{CachedStatement, Connection, OptionalExtension};
+
+use std::collections::HashMap;
+
+/// Strings of a dictionary table, cached by their IDs.
+struct Strings<'a> {
+    ids: HashMap<String, i64>,
+    /// Strings inserted since the last release, whose rows are gone if their block is rolled back.
+    block_strings: Vec<String>,
+    select: CachedStatement<'a>,
+    insert: CachedStatement<'a>,
+}
+
+impl<'a> Strings<'a> {
+    fn new(conn: &'a Connection, table: &str, column: &str) -> rusqlite::Result<Self> {
+        Ok(Strings {
+            ids: HashMap::new(),
+            block_strings: Vec::new(),
+            select: conn.prepare_cached(&format!("SELECT id FROM {table} WHERE {column} = ?1"))?,
+            insert: conn.prepare_cached(&format!("INSERT INTO {table} ({column}) VALUES (?1)"))?,
+        })
+    }
+
+    fn id(&mut self, string: &str) -> rusqlite::Result<i64> {
+        if let Some(id) = self.ids.get(string) {
+            return Ok(*id);
+        }
+
+        // Strings may already be in the table when applying changes
+        let id = match self.select.query_row([string], |row| row.get(0)).optional()? {
+            Some(id) => id,
+            None => {
+                self.block_strings.push(string.to_string());
+                self.insert.insert([string])?
+            }
+        };
+
+        self.ids.insert(string.to_string(), id);
+        Ok(id)
+    }
+
+    fn release(&mut self) {
+        self.block_strings.clear();
+    }
+
+    fn roll_back(&mut self) {
+        for string in self.block_strings.drain(..) {
+            self.ids.remove(&string);
+        }
+    }
+}
+
+/// Dictionary of tag keys and values in the `tag_keys` and `tag_values` tables, shared by all tag tables and blocks.
+pub struct TagDictionary<'a> {
+    keys: Strings<'a>,
+    values: Strings<'a>,
+}
+
+impl<'a> TagDictionary<'a> {
+    pub fn new(conn: &'a Connection) -> rusqlite::Result<Self> {
+        Ok(TagDictionary {
+            keys: Strings::new(conn, "tag_keys", "key")?,
+            values: Strings::new(conn, "tag_values", "value")?,
+        })
+    }
+
+    /// Returns the IDs of a tag's key and value, adding them to the dictionary if they're new.
+    pub fn ids(&mut self, key: &str, value: &str) -> rusqlite::Result<(i64, i64)> {
+        Ok((self.keys.id(key)?, self.values.id(value)?))
+    }
+
+    /// Keeps the cached IDs of the strings inserted since the last release.
+    pub fn release(&mut self) {
+        self.keys.release();
+        self.values.release();
+    }
+
+    /// Forgets the strings inserted since the last release, after their rows have been rolled back. Their IDs may be
+    /// assigned to other strings afterwards.
+    pub fn roll_back(&mut self) {
+        self.keys.roll_back();
+        self.values.roll_back();
+    }
+}
+
+#[cfg(test)]
+mod dictionary_tests {
+    use super::*;
+
+    #[test]
+    fn tag_ids() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tr = conn.transaction().unwrap();
+
+        tr.execute_batch(
+            "CREATE TABLE tag_keys (id INTEGER PRIMARY KEY, key TEXT NOT NULL UNIQUE);
+             CREATE TABLE tag_values (id INTEGER PRIMARY KEY, value TEXT NOT NULL UNIQUE);
+             INSERT INTO tag_keys (id, key) VALUES (5, 'name');",
+        )
+        .unwrap();
+
+        let mut dictionary = TagDictionary::new(&tr).unwrap();
+
+        assert_eq!(dictionary.ids("name", "Foo").unwrap(), (5, 1));
+        assert_eq!(dictionary.ids("highway", "Foo").unwrap(), (6, 1));
+        assert_eq!(dictionary.ids("name", "Bar").unwrap(), (5, 2));
+    }
+}
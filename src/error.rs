@@ -0,0 +1,116 @@
+use rosm_pbf_reader::pbf;
+
+use super::OsmPrimitive;
+use super::filter::ElementType;
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ElementErrorKind {
+    StringIndexOutOfBounds(i64),
+    InvalidUtf8(std::str::Utf8Error),
+    InvalidMemberType(i32),
+    /// The member IDs, types and roles of a relation have different lengths.
+    MemberCountMismatch,
+    /// The version of an element is missing, which is required in history mode and for snapshots.
+    MissingVersion,
+    /// The timestamp of an element is missing, which is required for snapshots.
+    MissingTimestamp,
+}
+
+/// Invalid data of an element in a primitive block.
+#[derive(Debug)]
+pub struct ElementError {
+    pub blob_index: usize,
+    pub element_type: ElementType,
+    pub id: i64,
+    pub kind: ElementErrorKind,
+}
+
+impl ElementError {
+    pub fn new<P: OsmPrimitive>(primitive: &P, blob_index: usize, kind: ElementErrorKind) -> ElementError {
+        ElementError {
+            blob_index,
+            element_type: P::ELEMENT_TYPE,
+            id: primitive.id(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ElementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid {} {} in block {}: ",
+            self.element_type.as_str(),
+            self.id,
+            self.blob_index
+        )?;
+
+        match &self.kind {
+            ElementErrorKind::StringIndexOutOfBounds(index) => write!(f, "string index {index} is out of bounds"),
+            ElementErrorKind::InvalidUtf8(error) => write!(f, "invalid UTF-8 string ({error})"),
+            ElementErrorKind::InvalidMemberType(member_type) => write!(f, "invalid member type {member_type}"),
+            ElementErrorKind::MemberCountMismatch => write!(f, "member IDs, types and roles don't match"),
+            ElementErrorKind::MissingVersion => write!(f, "missing version"),
+            ElementErrorKind::MissingTimestamp => write!(f, "missing timestamp"),
+        }
+    }
+}
+
+impl std::error::Error for ElementError {}
+
+/// The import has been stopped by SIGINT or SIGTERM.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The import has been interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Returns the string at `index` of a block's string table.
+pub fn string_at(string_table: &pbf::StringTable, index: i64) -> Result<&str, ElementErrorKind> {
+    let string = usize::try_from(index)
+        .ok()
+        .and_then(|index| string_table.s.get(index))
+        .ok_or(ElementErrorKind::StringIndexOutOfBounds(index))?;
+
+    std::str::from_utf8(string).map_err(ElementErrorKind::InvalidUtf8)
+}
+
+/// Returns whether `error` is caused by invalid data in a block, which may be skipped according to the error policy,
+/// instead of e.g. a database error.
+pub fn is_data_error(error: &anyhow::Error) -> bool {
+    error.is::<ElementError>() || error.is::<rosm_pbf_reader::Error>()
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn strings() {
+        let string_table = pbf::StringTable {
+            s: vec![b"".to_vec(), b"name".to_vec(), vec![0xFF]],
+        };
+
+        assert_eq!(string_at(&string_table, 1).unwrap(), "name");
+        assert!(matches!(
+            string_at(&string_table, 3),
+            Err(ElementErrorKind::StringIndexOutOfBounds(3))
+        ));
+        assert!(matches!(
+            string_at(&string_table, -1),
+            Err(ElementErrorKind::StringIndexOutOfBounds(-1))
+        ));
+        assert!(matches!(
+            string_at(&string_table, 2),
+            Err(ElementErrorKind::InvalidUtf8(_))
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::{Context, bail};
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ElementType {
+    Node,
+    Way,
+    Relation,
+}
+
+impl ElementType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ElementType::Node => "node",
+            ElementType::Way => "way",
+            ElementType::Relation => "relation",
+        }
+    }
+}
+
+/// Exact string, or a prefix if the pattern ends with `*`.
+#[derive(Clone, Debug)]
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Pattern {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => Pattern::Prefix(prefix.to_string()),
+            None => Pattern::Exact(pattern.to_string()),
+        }
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        match self {
+            Pattern::Exact(exact) => s == exact,
+            Pattern::Prefix(prefix) => s.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Expression {
+    element_types: Vec<ElementType>,
+    key: Pattern,
+    values: Vec<Pattern>,
+    negated: bool,
+}
+
+impl Expression {
+    fn parse(expression: &str) -> anyhow::Result<Expression> {
+        let (element_types, tag) = match expression.split_once('/') {
+            Some((types, tag)) if !types.is_empty() && types.chars().all(|c| "nwr".contains(c)) => {
+                let element_types = types
+                    .chars()
+                    .map(|c| match c {
+                        'n' => ElementType::Node,
+                        'w' => ElementType::Way,
+                        _ => ElementType::Relation,
+                    })
+                    .collect();
+
+                (element_types, tag)
+            }
+            _ => (
+                vec![ElementType::Node, ElementType::Way, ElementType::Relation],
+                expression,
+            ),
+        };
+
+        let (key, values, negated) = match tag.split_once('=') {
+            Some((key, values)) => match key.strip_suffix('!') {
+                Some(key) => (key, Some(values), true),
+                None => (key, Some(values), false),
+            },
+            None => (tag, None, false),
+        };
+
+        let key = key.trim();
+
+        if key.is_empty() {
+            bail!("Missing key in tag filter expression `{expression}`");
+        }
+
+        let values = match values {
+            Some(values) => values.split(',').map(|value| Pattern::new(value.trim())).collect(),
+            None => Vec::new(),
+        };
+
+        Ok(Expression {
+            element_types,
+            key: Pattern::new(key),
+            values,
+            negated,
+        })
+    }
+
+    fn matches(&self, element_type: ElementType, tags: &[(&str, &str)]) -> bool {
+        self.element_types.contains(&element_type)
+            && tags.iter().any(|(key, value)| {
+                self.key.matches(key)
+                    && (self.values.is_empty()
+                        || self.values.iter().any(|pattern| pattern.matches(value)) != self.negated)
+            })
+    }
+}
+
+/// Filter of elements by their tags, using [osmium tags-filter](https://docs.osmcode.org/osmium/latest/osmium-tags-filter.html)
+/// style expressions, like `w/highway`, `n/amenity=cafe,restaurant` or `r/type!=route`. An element matches the
+/// filter if it matches any of its expressions.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct TagFilter {
+    source: Vec<String>,
+    expressions: Vec<Expression>,
+}
+
+impl TryFrom<Vec<String>> for TagFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(source: Vec<String>) -> anyhow::Result<Self> {
+        let expressions = source
+            .iter()
+            .map(|expression| Expression::parse(expression))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(TagFilter { source, expressions })
+    }
+}
+
+impl From<TagFilter> for Vec<String> {
+    fn from(filter: TagFilter) -> Self {
+        filter.source
+    }
+}
+
+impl TagFilter {
+    pub fn matches(&self, element_type: ElementType, tags: &[(&str, &str)]) -> bool {
+        self.expressions
+            .iter()
+            .any(|expression| expression.matches(element_type, tags))
+    }
+}
+
+/// Set of tag key patterns: exact keys, globs with `*` and `?` wildcards like `tiger:*`, or regular expressions
+/// between slashes like `/^name:[a-z]{3}$/`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct KeyPatterns {
+    source: Vec<String>,
+    exact: HashSet<String>,
+    patterns: RegexSet,
+}
+
+impl TryFrom<Vec<String>> for KeyPatterns {
+    type Error = anyhow::Error;
+
+    fn try_from(source: Vec<String>) -> anyhow::Result<Self> {
+        let mut exact = HashSet::new();
+        let mut patterns = Vec::new();
+
+        for pattern in &source {
+            if let Some(regex) = pattern.strip_prefix('/').and_then(|pattern| pattern.strip_suffix('/')) {
+                patterns.push(regex.to_string());
+            } else if pattern.contains(['*', '?']) {
+                let glob: String = pattern
+                    .chars()
+                    .map(|c| match c {
+                        '*' => ".*".to_string(),
+                        '?' => ".".to_string(),
+                        _ => regex::escape(c.encode_utf8(&mut [0; 4])),
+                    })
+                    .collect();
+
+                patterns.push(format!("^{glob}$"));
+            } else {
+                exact.insert(pattern.clone());
+            }
+        }
+
+        let patterns = RegexSet::new(&patterns).context("Invalid tag key pattern")?;
+
+        Ok(KeyPatterns {
+            source,
+            exact,
+            patterns,
+        })
+    }
+}
+
+impl From<KeyPatterns> for Vec<String> {
+    fn from(patterns: KeyPatterns) -> Self {
+        patterns.source
+    }
+}
+
+impl KeyPatterns {
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.exact.contains(key) || self.patterns.is_match(key)
+    }
+}
+
+/// Collects the tags from a tag reader, failing on the first invalid one.
+pub fn read_tags<'a, Tags>(tags: Tags) -> anyhow::Result<Vec<(&'a str, &'a str)>>
+where
+    Tags: Iterator<
+        Item = (
+            Result<&'a str, rosm_pbf_reader::Error>,
+            Result<&'a str, rosm_pbf_reader::Error>,
+        ),
+    >,
+{
+    tags.map(|(key, value)| -> anyhow::Result<(&str, &str)> {
+        let key = key.context("Invalid tag key")?;
+        let value = value.context("Invalid tag value")?;
+        Ok((key, value))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    fn filter(expressions: &[&str]) -> TagFilter {
+        TagFilter::try_from(expressions.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn element_types() {
+        let filter = filter(&["w/highway", "nr/amenity=cafe,restaurant"]);
+
+        assert!(filter.matches(ElementType::Way, &[("highway", "primary")]));
+        assert!(!filter.matches(ElementType::Node, &[("highway", "crossing")]));
+        assert!(filter.matches(ElementType::Node, &[("name", "Foo"), ("amenity", "cafe")]));
+        assert!(filter.matches(ElementType::Relation, &[("amenity", "restaurant")]));
+        assert!(!filter.matches(ElementType::Node, &[("amenity", "bench")]));
+        assert!(!filter.matches(ElementType::Way, &[]));
+    }
+
+    #[test]
+    fn patterns() {
+        let filter = filter(&["name:*", "r/type!=route,multi*", "building=*"]);
+
+        assert!(filter.matches(ElementType::Node, &[("name:en", "Foo")]));
+        assert!(!filter.matches(ElementType::Node, &[("name", "Foo")]));
+        assert!(filter.matches(ElementType::Relation, &[("type", "boundary")]));
+        assert!(!filter.matches(ElementType::Relation, &[("type", "multipolygon")]));
+        assert!(filter.matches(ElementType::Way, &[("building", "yes")]));
+    }
+
+    #[test]
+    fn key_patterns() {
+        let patterns = KeyPatterns::try_from(
+            ["source", "tiger:*", "addr:??", "/^name:[a-z]{3}$/"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        assert!(patterns.matches("source"));
+        assert!(!patterns.matches("source:date"));
+        assert!(patterns.matches("tiger:cfcc"));
+        assert!(patterns.matches("addr:hu"));
+        assert!(!patterns.matches("addr:city"));
+        assert!(patterns.matches("name:deu"));
+        assert!(!patterns.matches("name:de"));
+
+        assert!(KeyPatterns::try_from(vec!["/(/".to_string()]).is_err());
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(TagFilter::try_from(vec!["n/=cafe".to_string()]).is_err());
+        assert!(TagFilter::try_from(vec!["".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+/// Node coordinates in nanodegrees as `(latitude, longitude)` pairs, keyed by node ID. All imported nodes are kept in
+/// memory, which takes roughly 30 to 50 bytes per node.
+///
+/// Locations inserted since the last call of `release` can be discarded with `roll_back`, if their block is rolled
+/// back.
+#[derive(Default)]
+pub struct NodeLocations {
+    locations: HashMap<i64, (i64, i64)>,
+    /// Nodes inserted since the last release, with the locations they've replaced.
+    block_nodes: Vec<(i64, Option<(i64, i64)>)>,
+}
+
+impl NodeLocations {
+    pub fn insert(&mut self, node_id: i64, coord: (i64, i64)) {
+        let replaced = self.locations.insert(node_id, coord);
+        self.block_nodes.push((node_id, replaced));
+    }
+
+    pub fn get(&self, node_id: &i64) -> Option<&(i64, i64)> {
+        self.locations.get(node_id)
+    }
+
+    /// Keeps the locations inserted since the last release.
+    pub fn release(&mut self) {
+        self.block_nodes.clear();
+    }
+
+    /// Discards the locations inserted since the last release.
+    pub fn roll_back(&mut self) {
+        for (node_id, replaced) in self.block_nodes.drain(..).rev() {
+            match replaced {
+                Some(coord) => self.locations.insert(node_id, coord),
+                None => self.locations.remove(&node_id),
+            };
+        }
+    }
+}
+
+/// Bounding box in nanodegrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: i64,
+    pub min_lon: i64,
+    pub max_lat: i64,
+    pub max_lon: i64,
+}
+
+impl BoundingBox {
+    /// Returns the bounding box of `coords`, or `None` if it's empty.
+    pub fn of(coords: &[(i64, i64)]) -> Option<BoundingBox> {
+        let (first, rest) = coords.split_first()?;
+
+        let mut bbox = BoundingBox {
+            min_lat: first.0,
+            min_lon: first.1,
+            max_lat: first.0,
+            max_lon: first.1,
+        };
+
+        for coord in rest {
+            bbox.extend(*coord);
+        }
+
+        Some(bbox)
+    }
+
+    /// Extends the bounding box to contain `coord`.
+    pub fn extend(&mut self, (lat, lon): (i64, i64)) {
+        self.min_lat = self.min_lat.min(lat);
+        self.min_lon = self.min_lon.min(lon);
+        self.max_lat = self.max_lat.max(lat);
+        self.max_lon = self.max_lon.max(lon);
+    }
+}
+
+/// A polygon with its rings' coordinates in nanodegrees. Rings are closed, i.e. their first and last coordinates
+/// are the same.
+pub struct Polygon {
+    pub outer: Vec<(i64, i64)>,
+    pub inners: Vec<Vec<(i64, i64)>>,
+}
+
+/// Returns twice the signed area of `ring`, which is positive if the ring is counterclockwise.
+pub fn ring_signed_area(ring: &[(i64, i64)]) -> i128 {
+    ring.windows(2)
+        .map(|edge| {
+            let ((lat1, lon1), (lat2, lon2)) = (edge[0], edge[1]);
+            lon1 as i128 * lat2 as i128 - lon2 as i128 * lat1 as i128
+        })
+        .sum()
+}
+
+/// Returns whether `point` lies inside `ring`, using the even-odd rule.
+pub fn ring_contains(ring: &[(i64, i64)], (lat, lon): (i64, i64)) -> bool {
+    let mut inside = false;
+
+    for edge in ring.windows(2) {
+        let ((lat1, lon1), (lat2, lon2)) = (edge[0], edge[1]);
+
+        if (lat1 > lat) != (lat2 > lat) {
+            let crossing_lon = lon1 as f64 + (lat - lat1) as f64 * (lon2 - lon1) as f64 / (lat2 - lat1) as f64;
+
+            if (lon as f64) < crossing_lon {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+const WKB_POINT: u32 = 1;
+const WKB_LINE_STRING: u32 = 2;
+const WKB_POLYGON: u32 = 3;
+const WKB_MULTI_POLYGON: u32 = 6;
+
+fn write_point(wkb: &mut Vec<u8>, (lat, lon): (i64, i64)) {
+    wkb.extend_from_slice(&(lon as f64 / 1e9).to_le_bytes());
+    wkb.extend_from_slice(&(lat as f64 / 1e9).to_le_bytes());
+}
+
+/// Encodes `coord` (in nanodegrees) as a little endian WKB point with longitude/latitude axis order.
+pub fn point_wkb(coord: (i64, i64)) -> Vec<u8> {
+    let mut wkb = Vec::with_capacity(21);
+
+    wkb.push(1);
+    wkb.extend_from_slice(&WKB_POINT.to_le_bytes());
+    write_point(&mut wkb, coord);
+
+    wkb
+}
+
+/// Encodes `coords` (in nanodegrees) as a little endian WKB line string with longitude/latitude axis order.
+pub fn line_string_wkb(coords: &[(i64, i64)]) -> Vec<u8> {
+    let mut wkb = Vec::with_capacity(9 + coords.len() * 16);
+
+    wkb.push(1);
+    wkb.extend_from_slice(&WKB_LINE_STRING.to_le_bytes());
+    wkb.extend_from_slice(&(coords.len() as u32).to_le_bytes());
+
+    for coord in coords {
+        write_point(&mut wkb, *coord);
+    }
+
+    wkb
+}
+
+fn write_ring(wkb: &mut Vec<u8>, ring: &[(i64, i64)], counterclockwise: bool) {
+    wkb.extend_from_slice(&(ring.len() as u32).to_le_bytes());
+
+    if (ring_signed_area(ring) > 0) == counterclockwise {
+        ring.iter().for_each(|coord| write_point(wkb, *coord));
+    } else {
+        ring.iter().rev().for_each(|coord| write_point(wkb, *coord));
+    }
+}
+
+/// Encodes `polygons` as a little endian WKB multipolygon with longitude/latitude axis order. Outer rings are written
+/// counterclockwise, inner rings clockwise.
+pub fn multi_polygon_wkb(polygons: &[Polygon]) -> Vec<u8> {
+    let mut wkb = Vec::new();
+
+    wkb.push(1);
+    wkb.extend_from_slice(&WKB_MULTI_POLYGON.to_le_bytes());
+    wkb.extend_from_slice(&(polygons.len() as u32).to_le_bytes());
+
+    for polygon in polygons {
+        wkb.push(1);
+        wkb.extend_from_slice(&WKB_POLYGON.to_le_bytes());
+        wkb.extend_from_slice(&(1 + polygon.inners.len() as u32).to_le_bytes());
+
+        write_ring(&mut wkb, &polygon.outer, true);
+
+        for inner in &polygon.inners {
+            write_ring(&mut wkb, inner, false);
+        }
+    }
+
+    wkb
+}
+
+/// SRS ID of WGS 84 in GeoPackages.
+pub const GEOPACKAGE_SRS_ID: i32 = 4326;
+
+/// Prepends a GeoPackage geometry header to `wkb`, with an `envelope` if given.
+pub fn geopackage_geometry(wkb: &[u8], envelope: Option<BoundingBox>) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(8 + 32 + wkb.len());
+
+    let envelope_flag = if envelope.is_some() { 1 << 1 } else { 0 };
+
+    blob.extend_from_slice(b"GP");
+    blob.push(0); // Version 1
+    blob.push(envelope_flag | 1); // Little endian
+    blob.extend_from_slice(&GEOPACKAGE_SRS_ID.to_le_bytes());
+
+    if let Some(bbox) = envelope {
+        for value in [bbox.min_lon, bbox.max_lon, bbox.min_lat, bbox.max_lat] {
+            blob.extend_from_slice(&(value as f64 / 1e9).to_le_bytes());
+        }
+    }
+
+    blob.extend_from_slice(wkb);
+    blob
+}
+
+#[cfg(test)]
+mod geometry_tests {
+    use super::*;
+
+    #[test]
+    fn bounding_box() {
+        assert_eq!(BoundingBox::of(&[]), None);
+        assert_eq!(
+            BoundingBox::of(&[(10, -5), (-3, 20), (4, 0)]),
+            Some(BoundingBox {
+                min_lat: -3,
+                min_lon: -5,
+                max_lat: 10,
+                max_lon: 20
+            })
+        );
+    }
+
+    #[test]
+    fn line_string() {
+        let wkb = line_string_wkb(&[(1_000_000_000, 2_000_000_000), (0, 500_000_000)]);
+
+        assert_eq!(wkb.len(), 9 + 2 * 16);
+        assert_eq!(wkb[..9], [1, 2, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(wkb[9..17], 2.0f64.to_le_bytes());
+        assert_eq!(wkb[17..25], 1.0f64.to_le_bytes());
+        assert_eq!(wkb[25..33], 0.5f64.to_le_bytes());
+        assert_eq!(wkb[33..41], 0.0f64.to_le_bytes());
+    }
+
+    #[test]
+    fn geopackage_header() {
+        let wkb = point_wkb((0, 0));
+
+        let blob = geopackage_geometry(&wkb, None);
+        assert_eq!(blob[..8], [b'G', b'P', 0, 1, 0xE6, 0x10, 0, 0]);
+        assert_eq!(blob[8..], wkb);
+
+        let bbox = BoundingBox::of(&[(0, 0)]).unwrap();
+        let blob = geopackage_geometry(&wkb, Some(bbox));
+        assert_eq!(blob[3], 3);
+        assert_eq!(blob.len(), 8 + 32 + wkb.len());
+    }
+
+    #[test]
+    fn ring_orientation_and_containment() {
+        let clockwise = [(0, 0), (10, 0), (10, 10), (0, 10), (0, 0)];
+
+        assert!(ring_signed_area(&clockwise) < 0);
+        assert!(ring_contains(&clockwise, (5, 5)));
+        assert!(!ring_contains(&clockwise, (5, 15)));
+        assert!(!ring_contains(&clockwise, (-1, 5)));
+    }
+}
@@ -1,25 +1,83 @@
-use anyhow::Context;
+use anyhow::{Context, bail};
 
 use rosm_pbf_reader::dense::{DenseNode, DenseNodeReader, new_dense_tag_reader};
 use rosm_pbf_reader::pbf;
 use rosm_pbf_reader::util::{normalize_coord, normalize_timestamp};
-use rosm_pbf_reader::{Block, BlockParser, DeltaValueReader, new_tag_reader, read_blob};
+use rosm_pbf_reader::{DeltaValueReader, new_tag_reader};
 
-use rusqlite::{Transaction, params};
+use rusqlite::{OpenFlags, ToSql, params};
 
 use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 mod config;
-use config::{Config, TableConfig, read_config};
+use config::{Config, ErrorPolicy, InterruptPolicy, TableConfig, read_config};
+
+mod area;
+use area::{AreaCollector, AreaSource};
+
+mod change;
+
+mod changesets;
+use changesets::Changesets;
+
+mod clip;
 
 mod db;
 
-fn process_header_block(block: &pbf::HeaderBlock, tr: &Transaction, config: &Config) -> rusqlite::Result<()> {
+mod dictionary;
+use dictionary::TagDictionary;
+
+mod error;
+use error::{ElementError, ElementErrorKind, Interrupted, is_data_error, string_at};
+
+mod filter;
+use filter::{ElementType, read_tags};
+
+mod geometry;
+use geometry::{BoundingBox, NodeLocations};
+
+mod pipeline;
+use pipeline::{BlobPosition, BlockError, DecodedBlock};
+
+mod progress;
+use progress::ImportProgress;
+
+mod replication;
+
+mod selection;
+use selection::Selection;
+
+mod snapshot;
+use snapshot::Snapshot;
+
+fn process_header_block(
+    block: &pbf::HeaderBlock,
+    conn: &rusqlite::Connection,
+    config: &Config,
+) -> rusqlite::Result<()> {
+    if !config.replication_state.skip
+        && let Some(sequence_number) = block.osmosis_replication_sequence_number
+    {
+        conn.execute(
+            "INSERT INTO replication_state (sequence_number, timestamp, base_url) VALUES (?1, ?2, ?3)",
+            params![
+                sequence_number,
+                block.osmosis_replication_timestamp,
+                block.osmosis_replication_base_url
+            ],
+        )?;
+    }
+
     if config.header.skip {
         return Ok(());
     }
 
-    let mut insert_info = tr.prepare_cached("INSERT INTO header (key, value) VALUES (?1, ?2)")?;
+    let mut insert_info = conn.prepare_cached("INSERT INTO header (key, value) VALUES (?1, ?2)")?;
 
     if let Some(bbox) = &block.bbox {
         insert_info.execute(params!["bbox_left", bbox.left])?;
@@ -62,12 +120,39 @@ fn process_header_block(block: &pbf::HeaderBlock, tr: &Transaction, config: &Con
     Ok(())
 }
 
+/// Checks the required features of the input's header block before anything is imported. Full-history inputs must be
+/// imported in history mode or as a snapshot, as the configuration is also used for applying changes to the dump.
+fn check_required_features(block: &pbf::HeaderBlock, config: &Config) -> anyhow::Result<()> {
+    for feature in &block.required_features {
+        match feature.as_str() {
+            "OsmSchema-V0.6" | "DenseNodes" => {}
+            "HistoricalInformation" => {
+                if !config.history && config.snapshot_at.is_none() {
+                    bail!("The input is a full-history file, set `history = true` or `snapshot_at` to import it");
+                }
+            }
+            _ => bail!("The input requires the feature `{feature}`, which isn't supported"),
+        }
+    }
+
+    Ok(())
+}
+
 pub trait OsmPrimitive {
+    const ELEMENT_TYPE: ElementType;
+
     fn id(&self) -> i64;
     fn info(&self) -> Option<&pbf::Info>;
+
+    /// Returns whether this is a deleted version of the element, which may only occur in full-history files.
+    fn is_deleted(&self) -> bool {
+        self.info().and_then(|info| info.visible) == Some(false)
+    }
 }
 
 impl OsmPrimitive for pbf::Node {
+    const ELEMENT_TYPE: ElementType = ElementType::Node;
+
     fn id(&self) -> i64 {
         self.id
     }
@@ -78,6 +163,8 @@ impl OsmPrimitive for pbf::Node {
 }
 
 impl OsmPrimitive for pbf::Way {
+    const ELEMENT_TYPE: ElementType = ElementType::Way;
+
     fn id(&self) -> i64 {
         self.id
     }
@@ -88,6 +175,8 @@ impl OsmPrimitive for pbf::Way {
 }
 
 impl OsmPrimitive for pbf::Relation {
+    const ELEMENT_TYPE: ElementType = ElementType::Relation;
+
     fn id(&self) -> i64 {
         self.id
     }
@@ -98,6 +187,8 @@ impl OsmPrimitive for pbf::Relation {
 }
 
 impl<'a> OsmPrimitive for DenseNode<'a> {
+    const ELEMENT_TYPE: ElementType = ElementType::Node;
+
     fn id(&self) -> i64 {
         self.id
     }
@@ -107,22 +198,32 @@ impl<'a> OsmPrimitive for DenseNode<'a> {
     }
 }
 
+/// Inserts the info of `primitive`. If users are normalized, the user is inserted into the `users` table as well.
 fn insert_info<P: OsmPrimitive>(
     primitive: &P,
     block: &pbf::PrimitiveBlock,
+    blob_index: usize,
     insert_stmt: &mut rusqlite::CachedStatement,
-) -> rusqlite::Result<()> {
+    insert_user: &mut Stmt,
+) -> anyhow::Result<()> {
     if let Some(info) = primitive.info() {
         let user = info
             .user_sid
-            .map(|string_id| std::str::from_utf8(block.stringtable.s[string_id as usize].as_ref()).unwrap());
+            .map(|string_id| string_at(&block.stringtable, string_id.into()))
+            .transpose()
+            .map_err(|kind| ElementError::new(primitive, blob_index, kind))?;
 
         let timestamp = info.timestamp.map(|ts| normalize_timestamp(ts, block));
 
+        if let (Some(insert_user), Some(user_id), Some(user)) = (insert_user, info.uid, user) {
+            insert_user.execute(params![user_id, user])?;
+        }
+
         insert_stmt.execute(params![
             primitive.id(),
             info.version,
             timestamp,
+            info.changeset,
             info.uid,
             user,
             info.visible
@@ -131,26 +232,407 @@ fn insert_info<P: OsmPrimitive>(
     Ok(())
 }
 
+/// Returns the version of `primitive` in history mode, where rows belonging to elements are keyed by their IDs and
+/// versions, or `None` otherwise.
+fn history_version<P: OsmPrimitive>(
+    primitive: &P,
+    blob_index: usize,
+    config: &Config,
+) -> Result<Option<i32>, ElementError> {
+    if !config.history {
+        return Ok(None);
+    }
+
+    let version = primitive.info().and_then(|info| info.version);
+    version
+        .map(Some)
+        .ok_or_else(|| ElementError::new(primitive, blob_index, ElementErrorKind::MissingVersion))
+}
+
+/// Executes an insert statement of a row belonging to an element, binding the element's ID and its version (if it's
+/// given) before `values`.
+fn insert_element_row(
+    insert_stmt: &mut rusqlite::CachedStatement,
+    id: i64,
+    version: Option<i32>,
+    values: &[&dyn ToSql],
+) -> rusqlite::Result<usize> {
+    let mut params: Vec<&dyn ToSql> = vec![&id];
+
+    if let Some(version) = &version {
+        params.push(version);
+    }
+
+    params.extend_from_slice(values);
+    insert_stmt.execute(&*params)
+}
+
+/// Inserts a tag of an element. If tags are normalized, its key and value are replaced by their IDs in the tag
+/// dictionary.
+fn insert_tag_row(
+    insert_stmt: &mut rusqlite::CachedStatement,
+    tag_dictionary: &mut Option<TagDictionary>,
+    id: i64,
+    version: Option<i32>,
+    (key, value): (&str, &str),
+) -> rusqlite::Result<usize> {
+    match tag_dictionary {
+        Some(tag_dictionary) => {
+            let (key_id, value_id) = tag_dictionary.ids(key, value)?;
+            insert_element_row(insert_stmt, id, version, params![key_id, value_id])
+        }
+        None => insert_element_row(insert_stmt, id, version, params![key, value]),
+    }
+}
+
+/// Returns the tags of an element as a JSON object for the `tags` column of element tables, without the skipped keys.
+fn tags_json<'a>(
+    config: &Config,
+    element_type: ElementType,
+    tags: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> String {
+    let object: serde_json::Map<String, serde_json::Value> = tags
+        .into_iter()
+        .filter(|(key, _)| !config.skips_tag_key(element_type, key))
+        .map(|(key, value)| (key.to_string(), value.into()))
+        .collect();
+
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Executes an insert statement of an element, binding its tags after `values` if they're stored as JSON.
+fn insert_element<'a, Tags>(
+    insert_stmt: &mut rusqlite::CachedStatement,
+    config: &Config,
+    (element_type, id, version): (ElementType, i64, Option<i32>),
+    values: &[&dyn ToSql],
+    tags: Tags,
+) -> anyhow::Result<()>
+where
+    Tags: Iterator<
+        Item = (
+            Result<&'a str, rosm_pbf_reader::Error>,
+            Result<&'a str, rosm_pbf_reader::Error>,
+        ),
+    >,
+{
+    let mut values = values.to_vec();
+    let tags = config
+        .json_tags
+        .then(|| read_tags(tags).map(|tags| tags_json(config, element_type, tags)))
+        .transpose()?;
+
+    if let Some(tags) = &tags {
+        values.push(tags);
+    }
+
+    insert_element_row(insert_stmt, id, version, &values)?;
+    Ok(())
+}
+
+/// Inserts the values of an element's name tags into the `names_fts` table, if it's enabled.
+fn insert_names<'a, Tags>(
+    insert_stmt: &mut Stmt,
+    config: &Config,
+    element_type: ElementType,
+    id: i64,
+    tags: Tags,
+) -> anyhow::Result<()>
+where
+    Tags: Iterator<
+        Item = (
+            Result<&'a str, rosm_pbf_reader::Error>,
+            Result<&'a str, rosm_pbf_reader::Error>,
+        ),
+    >,
+{
+    let (Some(insert_stmt), Some(names_fts)) = (insert_stmt, &config.names_fts) else {
+        return Ok(());
+    };
+
+    for (key, value) in tags {
+        let key = key?;
+
+        if names_fts.keys.matches(key) {
+            insert_stmt.execute(params![element_type.as_str(), id, key, value?])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the members of `relation` as `(type, id, role)` triples.
+fn relation_members<'a>(
+    relation: &pbf::Relation,
+    string_table: &'a pbf::StringTable,
+    blob_index: usize,
+) -> Result<Vec<(pbf::relation::MemberType, i64, &'a str)>, ElementError> {
+    let error = |kind| ElementError::new(relation, blob_index, kind);
+
+    if relation.types.len() != relation.memids.len() || relation.roles_sid.len() != relation.memids.len() {
+        return Err(error(ElementErrorKind::MemberCountMismatch));
+    }
+
+    DeltaValueReader::new(&relation.memids)
+        .zip(relation.types.iter().zip(&relation.roles_sid))
+        .map(|(member_id, (member_type, role_sid))| {
+            let member_type = pbf::relation::MemberType::try_from(*member_type)
+                .map_err(|_| error(ElementErrorKind::InvalidMemberType(*member_type)))?;
+            let role = string_at(string_table, (*role_sid).into()).map_err(error)?;
+
+            Ok((member_type, member_id, role))
+        })
+        .collect()
+}
+
+fn has_tag(
+    string_table: &pbf::StringTable,
+    keys: &[u32],
+    vals: &[u32],
+    key: &str,
+    values: &[&str],
+) -> anyhow::Result<bool> {
+    for (k, v) in new_tag_reader(string_table, keys, vals) {
+        if k? == key && values.contains(&v?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Bounding boxes of the features in the GeoPackage feature tables, written to `gpkg_contents` as their extents.
+#[derive(Clone, Copy, Default)]
+struct FeatureExtents {
+    nodes: Option<BoundingBox>,
+    ways: Option<BoundingBox>,
+    areas: Option<BoundingBox>,
+}
+
+impl FeatureExtents {
+    fn write(&self, conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+        db::update_geopackage_extents(
+            conn,
+            &[
+                ("node_features", self.nodes),
+                ("way_features", self.ways),
+                ("area_features", self.areas),
+            ],
+        )
+    }
+}
+
+/// Extends the extent of a feature table to contain `bbox`.
+fn extend_extent(extent: &mut Option<BoundingBox>, bbox: BoundingBox) {
+    match extent {
+        Some(extent) => {
+            extent.extend((bbox.min_lat, bbox.min_lon));
+            extent.extend((bbox.max_lat, bbox.max_lon));
+        }
+        None => *extent = Some(bbox),
+    }
+}
+
+/// Data collected across blocks during the import.
+#[derive(Default)]
+struct DumpState {
+    snapshot: Option<Snapshot>,
+    selection: Option<Selection>,
+    node_locations: Option<NodeLocations>,
+    areas: Option<AreaCollector>,
+    changesets: Option<Changesets>,
+    feature_extents: FeatureExtents,
+    /// Extents at the last released block, restored if a block is rolled back.
+    released_feature_extents: FeatureExtents,
+    error_count: usize,
+}
+
+impl DumpState {
+    /// Keeps the data collected from the current block, once its savepoint has been released.
+    fn release_block(&mut self, stmts: &mut InsertStatements) {
+        if let Some(tag_dictionary) = &mut stmts.tag_dictionary {
+            tag_dictionary.release();
+        }
+
+        if let Some(node_locations) = &mut self.node_locations {
+            node_locations.release();
+        }
+
+        if let Some(areas) = &mut self.areas {
+            areas.release();
+        }
+
+        if let Some(changesets) = &mut self.changesets {
+            changesets.release();
+        }
+
+        self.released_feature_extents = self.feature_extents;
+    }
+
+    /// Discards the data collected from the current block, whose savepoint has been rolled back.
+    fn roll_back_block(&mut self, stmts: &mut InsertStatements) {
+        if let Some(tag_dictionary) = &mut stmts.tag_dictionary {
+            tag_dictionary.roll_back();
+        }
+
+        if let Some(node_locations) = &mut self.node_locations {
+            node_locations.roll_back();
+        }
+
+        if let Some(areas) = &mut self.areas {
+            areas.roll_back();
+        }
+
+        if let Some(changesets) = &mut self.changesets {
+            changesets.roll_back();
+        }
+
+        self.feature_extents = self.released_feature_extents;
+    }
+
+    /// Adds an element to the summary of its changeset, if changesets are summarized.
+    fn add_to_changeset<P: OsmPrimitive>(
+        &mut self,
+        primitive: &P,
+        block: &pbf::PrimitiveBlock,
+        blob_index: usize,
+        coord: Option<(i64, i64)>,
+    ) -> anyhow::Result<()> {
+        let (Some(changesets), Some(info)) = (&mut self.changesets, primitive.info()) else {
+            return Ok(());
+        };
+
+        let Some(changeset_id) = info.changeset else {
+            return Ok(());
+        };
+
+        let user = || -> anyhow::Result<Option<String>> {
+            let user = info
+                .user_sid
+                .map(|string_id| string_at(&block.stringtable, string_id.into()))
+                .transpose()
+                .map_err(|kind| ElementError::new(primitive, blob_index, kind))?;
+
+            Ok(user.map(str::to_string))
+        };
+
+        changesets.add(changeset_id, P::ELEMENT_TYPE, info.uid, user, coord)
+    }
+
+    /// Returns whether an element should be imported, either according to the selection made before the import, or
+    /// by evaluating the tag filter. When importing a snapshot, only the elements' versions at its time are imported,
+    /// and elements without a version or timestamp are errors.
+    fn is_selected<'a, P, Tags>(
+        &self,
+        config: &Config,
+        (element_type, blob_index): (ElementType, usize),
+        primitive: &P,
+        tags: Tags,
+    ) -> anyhow::Result<bool>
+    where
+        P: OsmPrimitive,
+        Tags: Iterator<
+            Item = (
+                Result<&'a str, rosm_pbf_reader::Error>,
+                Result<&'a str, rosm_pbf_reader::Error>,
+            ),
+        >,
+    {
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.check(primitive, blob_index)?;
+        }
+
+        if self
+            .snapshot
+            .as_ref()
+            .is_some_and(|snapshot| !snapshot.contains(element_type, primitive))
+        {
+            Ok(false)
+        } else if let Some(selection) = &self.selection {
+            Ok(selection.contains(element_type, primitive.id()))
+        } else if let Some(filter) = &config.filter {
+            Ok(filter.expressions.matches(element_type, &read_tags(tags)?))
+        } else {
+            Ok(true)
+        }
+    }
+}
+
 fn process_primitive_block(
     block: &pbf::PrimitiveBlock,
+    blob_index: usize,
     config: &Config,
     stmts: &mut InsertStatements,
+    state: &mut DumpState,
 ) -> anyhow::Result<()> {
     let string_table = &block.stringtable;
 
     for group in &block.primitivegroup {
-        if let Some(insert_node) = &mut stmts.node {
+        if stmts.node.is_some()
+            || stmts.node_rtree.is_some()
+            || state.node_locations.is_some()
+            || state.changesets.is_some()
+            || stmts.name_fts.is_some()
+        {
             if let Some(dense_nodes) = &group.dense {
                 let nodes = DenseNodeReader::new(dense_nodes)?;
 
                 for node in nodes {
                     let node = node?;
 
+                    if !state.is_selected(
+                        config,
+                        (ElementType::Node, blob_index),
+                        &node,
+                        new_dense_tag_reader(string_table, node.key_value_indices),
+                    )? {
+                        continue;
+                    }
+
                     let coord = normalize_coord(node.lat, node.lon, block);
-                    insert_node.execute(params![node.id, coord.0, coord.1])?;
+                    let version = history_version(&node, blob_index, config)?;
+
+                    state.add_to_changeset(&node, block, blob_index, (!node.is_deleted()).then_some(coord))?;
+
+                    insert_names(
+                        &mut stmts.name_fts,
+                        config,
+                        ElementType::Node,
+                        node.id,
+                        new_dense_tag_reader(string_table, node.key_value_indices),
+                    )?;
+
+                    if let Some(insert_node) = &mut stmts.node {
+                        let coord = (!node.is_deleted()).then_some(coord);
+                        insert_element(
+                            insert_node,
+                            config,
+                            (ElementType::Node, node.id, version),
+                            params![coord.map(|coord| coord.0), coord.map(|coord| coord.1)],
+                            new_dense_tag_reader(string_table, node.key_value_indices),
+                        )?;
+                    }
+
+                    if let Some(insert_node_rtree) = &mut stmts.node_rtree {
+                        insert_node_rtree.execute(params![node.id, coord.0, coord.1])?;
+                    }
+
+                    if let Some(node_locations) = &mut state.node_locations {
+                        node_locations.insert(node.id, coord);
+                    }
+
+                    if let Some(insert_node_feature) = &mut stmts.node_feature
+                        && !node.key_value_indices.is_empty()
+                    {
+                        let point = geometry::geopackage_geometry(&geometry::point_wkb(coord), None);
+                        insert_node_feature.execute(params![node.id, point])?;
+                        extend_extent(
+                            &mut state.feature_extents.nodes,
+                            BoundingBox::of(&[coord]).expect("non-empty coordinates"),
+                        );
+                    }
 
                     if let Some(insert_node_info) = &mut stmts.node_info {
-                        insert_info(&node, block, insert_node_info)?;
+                        insert_info(&node, block, blob_index, insert_node_info, &mut stmts.user)?;
                     }
 
                     if let Some(insert_node_tag) = &mut stmts.node_tag {
@@ -159,94 +641,281 @@ fn process_primitive_block(
                         for (key, value) in tags {
                             let key = key?;
 
-                            if !config.skip_tag_keys.contains(key) {
-                                insert_node_tag.execute(params![node.id, key, value?])?;
+                            if !config.skips_tag_key(ElementType::Node, key) {
+                                insert_tag_row(
+                                    insert_node_tag,
+                                    &mut stmts.tag_dictionary,
+                                    node.id,
+                                    version,
+                                    (key, value?),
+                                )?;
                             }
                         }
                     }
                 }
             } else {
                 for node in &group.nodes {
+                    if !state.is_selected(
+                        config,
+                        (ElementType::Node, blob_index),
+                        node,
+                        new_tag_reader(string_table, &node.keys, &node.vals),
+                    )? {
+                        continue;
+                    }
+
                     let coord = normalize_coord(node.lat, node.lon, block);
-                    insert_node.execute(params![node.id, coord.0, coord.1])?;
+                    let version = history_version(node, blob_index, config)?;
+
+                    state.add_to_changeset(node, block, blob_index, (!node.is_deleted()).then_some(coord))?;
+
+                    insert_names(
+                        &mut stmts.name_fts,
+                        config,
+                        ElementType::Node,
+                        node.id,
+                        new_tag_reader(string_table, &node.keys, &node.vals),
+                    )?;
+
+                    if let Some(insert_node) = &mut stmts.node {
+                        let coord = (!node.is_deleted()).then_some(coord);
+                        insert_element(
+                            insert_node,
+                            config,
+                            (ElementType::Node, node.id, version),
+                            params![coord.map(|coord| coord.0), coord.map(|coord| coord.1)],
+                            new_tag_reader(string_table, &node.keys, &node.vals),
+                        )?;
+                    }
+
+                    if let Some(insert_node_rtree) = &mut stmts.node_rtree {
+                        insert_node_rtree.execute(params![node.id, coord.0, coord.1])?;
+                    }
+
+                    if let Some(node_locations) = &mut state.node_locations {
+                        node_locations.insert(node.id, coord);
+                    }
+
+                    if let Some(insert_node_feature) = &mut stmts.node_feature
+                        && !node.keys.is_empty()
+                    {
+                        let point = geometry::geopackage_geometry(&geometry::point_wkb(coord), None);
+                        insert_node_feature.execute(params![node.id, point])?;
+                        extend_extent(
+                            &mut state.feature_extents.nodes,
+                            BoundingBox::of(&[coord]).expect("non-empty coordinates"),
+                        );
+                    }
 
                     if let Some(insert_node_tag) = &mut stmts.node_tag {
                         let tags = new_tag_reader(string_table, &node.keys, &node.vals);
 
                         for (key, value) in tags {
                             let key = key?;
-                            if !config.skip_tag_keys.contains(key) {
-                                insert_node_tag.execute(params![node.id, key, value?])?;
+                            if !config.skips_tag_key(ElementType::Node, key) {
+                                insert_tag_row(
+                                    insert_node_tag,
+                                    &mut stmts.tag_dictionary,
+                                    node.id,
+                                    version,
+                                    (key, value?),
+                                )?;
                             }
                         }
                     }
 
                     if let Some(insert_node_info) = &mut stmts.node_info {
-                        insert_info(node, block, insert_node_info)?;
+                        insert_info(node, block, blob_index, insert_node_info, &mut stmts.user)?;
                     }
                 }
             }
         }
 
-        if let Some(insert_way) = &mut stmts.way {
+        if stmts.way.is_some()
+            || stmts.way_rtree.is_some()
+            || stmts.way_feature.is_some()
+            || state.areas.is_some()
+            || state.changesets.is_some()
+            || stmts.name_fts.is_some()
+        {
             for way in &group.ways {
-                insert_way.execute(params![way.id])?;
+                if !state.is_selected(
+                    config,
+                    (ElementType::Way, blob_index),
+                    way,
+                    new_tag_reader(string_table, &way.keys, &way.vals),
+                )? {
+                    continue;
+                }
+
+                let version = history_version(way, blob_index, config)?;
+
+                state.add_to_changeset(way, block, blob_index, None)?;
+
+                insert_names(
+                    &mut stmts.name_fts,
+                    config,
+                    ElementType::Way,
+                    way.id,
+                    new_tag_reader(string_table, &way.keys, &way.vals),
+                )?;
+
+                if let Some(insert_way) = &mut stmts.way {
+                    insert_element(
+                        insert_way,
+                        config,
+                        (ElementType::Way, way.id, version),
+                        params![],
+                        new_tag_reader(string_table, &way.keys, &way.vals),
+                    )?;
+                }
 
                 if let Some(insert_way_tag) = &mut stmts.way_tag {
                     let tags = new_tag_reader(string_table, &way.keys, &way.vals);
 
                     for (key, value) in tags {
                         let key = key?;
-                        if !config.skip_tag_keys.contains(key) {
-                            insert_way_tag.execute(params![way.id, key, value?])?;
+                        if !config.skips_tag_key(ElementType::Way, key) {
+                            insert_tag_row(
+                                insert_way_tag,
+                                &mut stmts.tag_dictionary,
+                                way.id,
+                                version,
+                                (key, value?),
+                            )?;
                         }
                     }
                 }
 
                 if let Some(insert_way_info) = &mut stmts.way_info {
-                    insert_info(way, block, insert_way_info)?;
+                    insert_info(way, block, blob_index, insert_way_info, &mut stmts.user)?;
                 }
 
                 if let Some(insert_way_ref) = &mut stmts.way_ref {
                     let refs = DeltaValueReader::new(&way.refs);
 
-                    for node_id in refs {
-                        insert_way_ref.execute(params![way.id, node_id])?;
+                    for (i, node_id) in refs.enumerate() {
+                        insert_element_row(insert_way_ref, way.id, version, params![i as i64, node_id])?;
+                    }
+                }
+
+                if let Some(node_locations) = &state.node_locations
+                    && (stmts.way_geometry.is_some() || stmts.way_rtree.is_some() || stmts.way_feature.is_some())
+                {
+                    let coords: Option<Vec<(i64, i64)>> = DeltaValueReader::new(&way.refs)
+                        .map(|node_id| node_locations.get(&node_id).copied())
+                        .collect();
+
+                    // Ways referencing nodes missing from the input are skipped
+                    if let Some(coords) = coords.filter(|coords| coords.len() >= 2) {
+                        let bbox = BoundingBox::of(&coords).expect("non-empty coordinates");
+                        let wkb = geometry::line_string_wkb(&coords);
+
+                        if let Some(insert_way_rtree) = &mut stmts.way_rtree {
+                            insert_way_rtree.execute(params![
+                                way.id,
+                                bbox.min_lat,
+                                bbox.max_lat,
+                                bbox.min_lon,
+                                bbox.max_lon
+                            ])?;
+                        }
+
+                        if let Some(insert_way_feature) = &mut stmts.way_feature {
+                            let line_string = geometry::geopackage_geometry(&wkb, Some(bbox));
+                            insert_way_feature.execute(params![way.id, line_string])?;
+                            extend_extent(&mut state.feature_extents.ways, bbox);
+                        }
+
+                        if let Some(insert_way_geometry) = &mut stmts.way_geometry {
+                            insert_way_geometry.execute(params![
+                                way.id,
+                                wkb,
+                                bbox.min_lat,
+                                bbox.min_lon,
+                                bbox.max_lat,
+                                bbox.max_lon
+                            ])?;
+                        }
                     }
                 }
+
+                if let Some(areas) = &mut state.areas {
+                    let is_area = area::is_area(read_tags(new_tag_reader(string_table, &way.keys, &way.vals))?);
+                    areas.add_way(way.id, DeltaValueReader::new(&way.refs).collect(), is_area);
+                }
             }
         }
 
-        if let Some(insert_relation) = &mut stmts.relation {
+        if stmts.relation.is_some() || state.areas.is_some() || state.changesets.is_some() || stmts.name_fts.is_some() {
             for relation in &group.relations {
-                insert_relation.execute(params![relation.id])?;
+                if !state.is_selected(
+                    config,
+                    (ElementType::Relation, blob_index),
+                    relation,
+                    new_tag_reader(string_table, &relation.keys, &relation.vals),
+                )? {
+                    continue;
+                }
+
+                let version = history_version(relation, blob_index, config)?;
+
+                state.add_to_changeset(relation, block, blob_index, None)?;
+
+                insert_names(
+                    &mut stmts.name_fts,
+                    config,
+                    ElementType::Relation,
+                    relation.id,
+                    new_tag_reader(string_table, &relation.keys, &relation.vals),
+                )?;
+
+                if let Some(insert_relation) = &mut stmts.relation {
+                    insert_element(
+                        insert_relation,
+                        config,
+                        (ElementType::Relation, relation.id, version),
+                        params![],
+                        new_tag_reader(string_table, &relation.keys, &relation.vals),
+                    )?;
+                }
 
                 if let Some(insert_relation_tag) = &mut stmts.relation_tag {
                     let tags = new_tag_reader(string_table, &relation.keys, &relation.vals);
 
                     for (key, value) in tags {
                         let key = key?;
-                        if !config.skip_tag_keys.contains(key) {
-                            insert_relation_tag.execute(params![relation.id, key, value?])?;
+                        if !config.skips_tag_key(ElementType::Relation, key) {
+                            insert_tag_row(
+                                insert_relation_tag,
+                                &mut stmts.tag_dictionary,
+                                relation.id,
+                                version,
+                                (key, value?),
+                            )?;
                         }
                     }
                 }
 
                 if let Some(insert_relation_info) = &mut stmts.relation_info {
-                    insert_info(relation, block, insert_relation_info)?;
+                    insert_info(relation, block, blob_index, insert_relation_info, &mut stmts.user)?;
                 }
 
-                if let Some(insert_relation_member) = &mut stmts.relation_member {
-                    let memids = DeltaValueReader::new(&relation.memids);
+                let members = if stmts.relation_member.is_some() || state.areas.is_some() {
+                    relation_members(relation, string_table, blob_index)?
+                } else {
+                    Vec::new()
+                };
 
-                    for (i, member_id) in memids.enumerate() {
+                if let Some(insert_relation_member) = &mut stmts.relation_member {
+                    for (i, (member_type, member_id, role)) in members.iter().enumerate() {
                         use pbf::relation::MemberType;
 
                         let mut node_id = None;
                         let mut way_id = None;
                         let mut rel_id = None;
 
-                        match MemberType::try_from(relation.types[i]).expect("invalid MemberType enum") {
+                        match member_type {
                             MemberType::Node => {
                                 node_id = Some(member_id);
                             }
@@ -258,168 +927,1261 @@ fn process_primitive_block(
                             }
                         }
 
-                        let string_id: usize = relation.roles_sid[i].try_into().expect("negative string index");
-                        let role = std::str::from_utf8(string_table.s[string_id].as_ref())?;
-
-                        insert_relation_member.execute(params![relation.id, node_id, way_id, rel_id, role])?;
+                        insert_element_row(
+                            insert_relation_member,
+                            relation.id,
+                            version,
+                            params![node_id, way_id, rel_id, role, i as i64],
+                        )?;
                     }
                 }
+
+                if let Some(areas) = &mut state.areas
+                    && has_tag(
+                        string_table,
+                        &relation.keys,
+                        &relation.vals,
+                        "type",
+                        &["multipolygon", "boundary"],
+                    )?
+                {
+                    let way_members = members
+                        .iter()
+                        .filter(|(member_type, _, _)| *member_type == pbf::relation::MemberType::Way)
+                        .map(|(_, member_id, role)| (*member_id, *role == "inner"))
+                        .collect();
+
+                    areas.add_relation(relation.id, way_members);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles an error of the block at `position` according to the configured error policy, returning it if the import
+/// should be aborted.
+fn handle_block_error(
+    position: BlobPosition,
+    error: &str,
+    config: &Config,
+    stmts: &mut InsertStatements,
+    state: &mut DumpState,
+) -> anyhow::Result<()> {
+    let message = format!("{error} (blob {} at offset {})", position.index, position.offset);
+
+    match config.on_error {
+        ErrorPolicy::Fail => bail!(message),
+        ErrorPolicy::Skip => println!("{message}"),
+        ErrorPolicy::Record => {
+            println!("{message}");
+
+            if let Some(insert_import_error) = &mut stmts.import_error {
+                insert_import_error.execute(params![position.index as i64, position.offset as i64, error])?;
             }
         }
     }
 
+    state.error_count += 1;
     Ok(())
 }
 
+fn build_areas(
+    areas: &AreaCollector,
+    node_locations: &NodeLocations,
+    stmts: &mut InsertStatements,
+    feature_extent: &mut Option<BoundingBox>,
+) -> anyhow::Result<()> {
+    let source_ids = |source: AreaSource| match source {
+        AreaSource::Way(way_id) => (Some(way_id), None),
+        AreaSource::Relation(relation_id) => (None, Some(relation_id)),
+    };
+
+    let (Some(insert_area), Some(insert_area_error)) = (&mut stmts.area, &mut stmts.area_error) else {
+        return Ok(());
+    };
+
+    areas.build(
+        node_locations,
+        |source, polygons| {
+            let (way_id, relation_id) = source_ids(source);
+            let outers: Vec<(i64, i64)> = polygons.iter().flat_map(|polygon| polygon.outer.clone()).collect();
+            let bbox = BoundingBox::of(&outers).expect("non-empty outer rings");
+            let wkb = geometry::multi_polygon_wkb(polygons);
+
+            if let Some(insert_area_feature) = &mut stmts.area_feature {
+                let multi_polygon = geometry::geopackage_geometry(&wkb, Some(bbox));
+                insert_area_feature.execute(params![way_id, relation_id, multi_polygon])?;
+                extend_extent(feature_extent, bbox);
+            }
+
+            insert_area.execute(params![
+                way_id,
+                relation_id,
+                wkb,
+                bbox.min_lat,
+                bbox.min_lon,
+                bbox.max_lat,
+                bbox.max_lon
+            ])?;
+            Ok(())
+        },
+        |source, error| {
+            let (way_id, relation_id) = source_ids(source);
+            insert_area_error.execute(params![way_id, relation_id, error])?;
+            Ok(())
+        },
+    )
+}
+
 type Stmt<'a> = Option<rusqlite::CachedStatement<'a>>;
 
 struct InsertStatements<'a> {
     node: Stmt<'a>,
     node_tag: Stmt<'a>,
     node_info: Stmt<'a>,
+    node_rtree: Stmt<'a>,
 
     way: Stmt<'a>,
     way_tag: Stmt<'a>,
     way_info: Stmt<'a>,
     way_ref: Stmt<'a>,
+    way_geometry: Stmt<'a>,
+    way_rtree: Stmt<'a>,
 
     relation: Stmt<'a>,
     relation_tag: Stmt<'a>,
     relation_info: Stmt<'a>,
     relation_member: Stmt<'a>,
+
+    area: Stmt<'a>,
+    area_error: Stmt<'a>,
+
+    node_feature: Stmt<'a>,
+    way_feature: Stmt<'a>,
+    area_feature: Stmt<'a>,
+
+    tag_dictionary: Option<TagDictionary<'a>>,
+    user: Stmt<'a>,
+    changeset: Stmt<'a>,
+    name_fts: Stmt<'a>,
+    import_error: Stmt<'a>,
 }
 
-fn prepare_insert_statements<'a>(tr: &'a Transaction, config: &Config) -> rusqlite::Result<InsertStatements<'a>> {
+fn prepare_insert_statements<'a>(
+    conn: &'a rusqlite::Connection,
+    config: &Config,
+) -> rusqlite::Result<InsertStatements<'a>> {
     let stmt = |sql: &str, table: &TableConfig, dependent_table: &TableConfig| {
         if !table.skip && !dependent_table.skip {
-            tr.prepare_cached(sql).map(Some)
+            conn.prepare_cached(sql).map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+
+    let optional_stmt = |sql: &str, table: &Option<TableConfig>, dependent_table: &TableConfig| match table {
+        Some(table) => stmt(sql, table, dependent_table),
+        None => Ok(None),
+    };
+
+    let geopackage_stmt = |sql: &str, enabled: bool| {
+        if config.geopackage && enabled {
+            conn.prepare_cached(sql).map(Some)
         } else {
             Ok(None)
         }
     };
 
+    // Rows belonging to elements start with the element's ID, followed by its version in history mode
+    let element_insert = |table: &str, columns: &[&str]| {
+        let mut columns = columns.to_vec();
+
+        if config.history {
+            columns.insert(1, "version");
+        }
+
+        let values: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+        format!(
+            "INSERT INTO {table} ({}) VALUES ({})",
+            columns.join(", "),
+            values.join(", ")
+        )
+    };
+
+    // Tags are either stored in the elements' rows as JSON, or in the tag tables
+    let tags_column: &[&str] = if config.json_tags { &["tags"] } else { &[] };
+
+    let tag_columns: &[&str] = if config.normalize_tags {
+        &["key_id", "value_id"]
+    } else {
+        &["key", "value"]
+    };
+
+    let tag_stmt = |table: &str, id_column: &str, tag_table: &TableConfig, dependent_table: &TableConfig| {
+        if config.json_tags {
+            Ok(None)
+        } else {
+            stmt(
+                &element_insert(table, &[&[id_column], tag_columns].concat()),
+                tag_table,
+                dependent_table,
+            )
+        }
+    };
+
+    // Info rows are always bound with the user name as `?6`, which is left out if users are normalized
+    let info_insert = |table: &str, id_column: &str| {
+        let (user_column, user_value) = if config.normalize_users {
+            ("", "")
+        } else {
+            (" user,", " ?6,")
+        };
+
+        format!(
+            "INSERT INTO {table} ({id_column}, version, timestamp, changeset, user_id,{user_column} visible) VALUES (?1, ?2, ?3, ?4, ?5,{user_value} ?7)"
+        )
+    };
+
     Ok(InsertStatements {
         node: stmt(
-            "INSERT INTO nodes (id, lat, lon) VALUES (?1, ?2, ?3)",
+            &element_insert("nodes", &[&["id", "lat", "lon"], tags_column].concat()),
             &config.nodes,
             &config.nodes,
         )?,
-        node_tag: stmt(
-            "INSERT INTO node_tags (node_id, key, value) VALUES (?1, ?2, ?3)",
-            &config.node_tags,
-            &config.nodes,
-        )?,
-        node_info: stmt(
-            "INSERT INTO node_info (node_id, version, timestamp, user_id, user, visible) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            &config.node_info,
+        node_tag: tag_stmt("node_tags", "node_id", &config.node_tags.table, &config.nodes)?,
+        node_info: stmt(&info_insert("node_info", "node_id"), &config.node_info, &config.nodes)?,
+        node_rtree: optional_stmt(
+            "INSERT INTO nodes_rtree (id, min_lat, max_lat, min_lon, max_lon) VALUES (?1, ?2, ?2, ?3, ?3)",
+            &config.nodes_rtree,
             &config.nodes,
         )?,
 
-        way: stmt("INSERT INTO ways (id) VALUES (?1)", &config.ways, &config.ways)?,
-        way_tag: stmt(
-            "INSERT INTO way_tags (way_id, key, value) VALUES (?1, ?2, ?3)",
-            &config.way_tags,
+        way: stmt(
+            &element_insert("ways", &[&["id"], tags_column].concat()),
             &config.ways,
-        )?,
-        way_info: stmt(
-            "INSERT INTO way_info (way_id, version, timestamp, user_id, user, visible) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            &config.way_info,
             &config.ways,
         )?,
+        way_tag: tag_stmt("way_tags", "way_id", &config.way_tags.table, &config.ways)?,
+        way_info: stmt(&info_insert("way_info", "way_id"), &config.way_info, &config.ways)?,
         way_ref: stmt(
-            "INSERT INTO way_refs (way_id, ref_node_id) VALUES (?1, ?2)",
+            &element_insert("way_refs", &["way_id", "sequence_id", "ref_node_id"]),
             &config.way_refs,
             &config.ways,
         )?,
+        way_geometry: optional_stmt(
+            "INSERT INTO way_geometries (way_id, geometry, min_lat, min_lon, max_lat, max_lon) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            &config.way_geometries,
+            &config.ways,
+        )?,
+        way_rtree: optional_stmt(
+            "INSERT INTO ways_rtree (id, min_lat, max_lat, min_lon, max_lon) VALUES (?1, ?2, ?3, ?4, ?5)",
+            &config.ways_rtree,
+            &config.ways,
+        )?,
 
         relation: stmt(
-            "INSERT INTO relations (id) VALUES (?1)",
+            &element_insert("relations", &[&["id"], tags_column].concat()),
             &config.relations,
             &config.relations,
         )?,
-        relation_tag: stmt(
-            "INSERT INTO relation_tags (relation_id, key, value) VALUES (?1, ?2, ?3)",
-            &config.relation_tags,
+        relation_tag: tag_stmt(
+            "relation_tags",
+            "relation_id",
+            &config.relation_tags.table,
             &config.relations,
         )?,
         relation_info: stmt(
-            "INSERT INTO relation_info (relation_id, version, timestamp, user_id, user, visible) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            &info_insert("relation_info", "relation_id"),
             &config.relation_info,
             &config.relations,
         )?,
         relation_member: stmt(
-            "INSERT INTO relation_members (relation_id, member_node_id, member_way_id, member_relation_id, role) VALUES (?1, ?2, ?3, ?4, ?5)",
+            &element_insert(
+                "relation_members",
+                &[
+                    "relation_id",
+                    "member_node_id",
+                    "member_way_id",
+                    "member_relation_id",
+                    "role",
+                    "sequence_id",
+                ],
+            ),
             &config.relation_members,
             &config.relations,
         )?,
+
+        area: optional_stmt(
+            "INSERT INTO areas (way_id, relation_id, geometry, min_lat, min_lon, max_lat, max_lon) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &config.areas,
+            &TableConfig::default(),
+        )?,
+        area_error: optional_stmt(
+            "INSERT INTO area_errors (way_id, relation_id, error) VALUES (?1, ?2, ?3)",
+            &config.areas,
+            &TableConfig::default(),
+        )?,
+
+        node_feature: geopackage_stmt("INSERT INTO node_features (fid, geom) VALUES (?1, ?2)", true)?,
+        way_feature: geopackage_stmt("INSERT INTO way_features (fid, geom) VALUES (?1, ?2)", true)?,
+        area_feature: geopackage_stmt(
+            "INSERT INTO area_features (way_id, relation_id, geom) VALUES (?1, ?2, ?3)",
+            config.areas.as_ref().is_some_and(|table| !table.skip),
+        )?,
+
+        tag_dictionary: if config.normalize_tags {
+            Some(TagDictionary::new(conn)?)
+        } else {
+            None
+        },
+        user: if config.normalize_users {
+            Some(conn.prepare_cached("INSERT OR IGNORE INTO users (user_id, user) VALUES (?1, ?2)")?)
+        } else {
+            None
+        },
+        changeset: optional_stmt(
+            "INSERT INTO changesets (id, user_id, user, node_count, way_count, relation_count, min_lat, min_lon, max_lat, max_lon) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            &config.changesets,
+            &TableConfig::default(),
+        )?,
+        name_fts: if config.names_fts.is_some() {
+            Some(conn.prepare_cached(
+                "INSERT INTO names_fts (element_type, element_id, key, value) VALUES (?1, ?2, ?3, ?4)",
+            )?)
+        } else {
+            None
+        },
+        import_error: if config.on_error == ErrorPolicy::Record {
+            Some(conn.prepare_cached("INSERT INTO import_errors (blob_index, blob_offset, error) VALUES (?1, ?2, ?3)")?)
+        } else {
+            None
+        },
     })
 }
 
-fn dump<Input: std::io::Read>(
+/// Sets `flag` on SIGINT and SIGTERM until the returned handlers are unregistered. A second signal terminates the
+/// process right away.
+fn register_interrupt_handlers(flag: &Arc<AtomicBool>) -> std::io::Result<Vec<signal_hook::SigId>> {
+    let mut handlers = Vec::new();
+
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        handlers.push(signal_hook::flag::register_conditional_shutdown(
+            signal,
+            EXIT_CODE_INTERRUPTED.into(),
+            Arc::clone(flag),
+        )?);
+        handlers.push(signal_hook::flag::register(signal, Arc::clone(flag))?);
+    }
+
+    Ok(handlers)
+}
+
+/// Imports the input into the database, returning the number of skipped or recorded errors.
+///
+/// When resuming an import, the input must be positioned at the first blob which hasn't been imported yet. If the
+/// import is interrupted by SIGINT or SIGTERM, it's committed or rolled back according to `on_interrupt` and
+/// [`Interrupted`] is returned.
+fn dump<Input: std::io::Read + Send>(
     input_pbf: &mut Input,
     conn: &mut rusqlite::Connection,
     config: &Config,
-) -> anyhow::Result<()> {
+    (snapshot, selection): (Option<Snapshot>, Option<Selection>),
+    resume: Option<ImportProgress>,
+) -> anyhow::Result<usize> {
+    if config.history
+        && (config.nodes_rtree.is_some()
+            || config.way_geometries.is_some()
+            || config.ways_rtree.is_some()
+            || config.areas.is_some()
+            || config.geopackage
+            || config.names_fts.is_some())
+    {
+        bail!(
+            "R*Tree indices, way geometries, areas, GeoPackage features and name search aren't supported in history mode"
+        );
+    }
+
+    if config.json_tags && config.normalize_tags {
+        bail!("Tags can't be both stored as JSON and normalized");
+    }
+
+    if resume.is_some()
+        && (config.way_geometries.is_some()
+            || config.ways_rtree.is_some()
+            || config.areas.is_some()
+            || config.changesets.is_some()
+            || config.geopackage)
     {
+        bail!(
+            "Imports with way geometries, areas, changesets or GeoPackage features can't be resumed, as they're built from the whole input"
+        );
+    }
+
+    let previous_pragmas = db::apply_pragmas(conn, &config.sqlite)?;
+
+    if resume.is_none() {
         let tr = conn.transaction()?;
         db::create_tables(&tr, config)?;
         tr.commit()?;
     }
 
-    conn.execute("PRAGMA synchronous = OFF", [])?;
-    conn.query_row_and_then("PRAGMA journal_mode = MEMORY", [], |_row| -> rusqlite::Result<()> {
-        Ok(())
-    })?;
+    if selection.is_some() || config.filter.is_some() {
+        // Selected ways may reference nodes which aren't imported
+        conn.execute("PRAGMA foreign_keys = OFF", [])?;
+    }
 
-    {
-        let tr = conn.transaction()?;
+    let start = resume.unwrap_or_default();
 
-        let mut stmts = prepare_insert_statements(&tr, config)?;
+    let state = DumpState {
+        snapshot,
+        selection,
+        error_count: start.error_count,
+        ..Default::default()
+    };
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handlers = register_interrupt_handlers(&interrupted)?;
+
+    conn.execute_batch("BEGIN")?;
+    let result = import_blocks(input_pbf, conn, config, state, start, &interrupted);
+
+    // Indices are built after the data is committed, so the process can be terminated as usual from now on
+    for handler in handlers {
+        signal_hook::low_level::unregister(handler);
+    }
+
+    let progress = match result {
+        Ok(ImportEnd::Finished(progress)) => progress,
+        Ok(ImportEnd::Interrupted(progress)) => {
+            if !config.import_metadata.skip {
+                progress::write_metadata(conn, &progress, false)?;
+            }
+
+            db::restore_pragmas(conn, &previous_pragmas)?;
+            return Err(Interrupted.into());
+        }
+        Err(error) => {
+            if !conn.is_autocommit() {
+                conn.execute_batch("ROLLBACK")?;
+            }
+
+            db::restore_pragmas(conn, &previous_pragmas)?;
+            return Err(error);
+        }
+    };
+
+    db::create_indices(conn, config)?;
+
+    if config.commit_every.is_some() {
+        progress::finish(conn)?;
+    }
+
+    if !config.import_metadata.skip {
+        progress::write_metadata(conn, &progress, true)?;
+    }
+
+    db::restore_pragmas(conn, &previous_pragmas)?;
+
+    Ok(progress.error_count)
+}
+
+/// How the import of the blobs has ended, with the progress of the committed data.
+enum ImportEnd {
+    Finished(ImportProgress),
+    Interrupted(ImportProgress),
+}
+
+/// Imports the blobs of the input starting at `start` in the open transaction, and commits it. If `commit_every` is
+/// set, the transaction is also committed periodically, along with the progress of the import.
+///
+/// Reading stops once `interrupted` is set, and the transaction is committed or rolled back according to
+/// `on_interrupt`. Areas and changesets aren't written then, as they'd be incomplete.
+fn import_blocks<Input: std::io::Read + Send>(
+    input_pbf: &mut Input,
+    conn: &rusqlite::Connection,
+    config: &Config,
+    mut state: DumpState,
+    start: ImportProgress,
+    interrupted: &AtomicBool,
+) -> anyhow::Result<ImportEnd> {
+    let mut stmts = prepare_insert_statements(conn, config)?;
+
+    state.areas = stmts.area.is_some().then(AreaCollector::default);
+    state.changesets = stmts.changeset.is_some().then(Changesets::default);
+
+    if stmts.way_geometry.is_some() || stmts.way_rtree.is_some() || state.areas.is_some() || config.geopackage {
+        state.node_locations = Some(NodeLocations::default());
+    }
 
-        let mut block_parser = BlockParser::default();
+    let mut progress = start;
+    let mut committed = start;
+
+    let result = pipeline::decode_blocks_from(
+        input_pbf,
+        (start.blob_index, start.blob_offset),
+        config.worker_threads,
+        |position, result| {
+            if interrupted.load(Ordering::Relaxed) {
+                return Err(Interrupted.into());
+            }
 
-        while let Some(result) = read_blob(input_pbf) {
             match result {
-                Ok(raw_block) => match block_parser.parse_block(raw_block) {
-                    Ok(block) => match block {
-                        Block::Header(header_block) => process_header_block(&header_block, &tr, config)?,
-                        Block::Primitive(primitive_block) => {
-                            process_primitive_block(&primitive_block, config, &mut stmts)?;
+                Ok(DecodedBlock::Header(header_block)) => process_header_block(&header_block, conn, config)?,
+                Ok(DecodedBlock::Primitive(primitive_block)) => {
+                    // Blocks with invalid data are rolled back as a whole, before handling the error
+                    conn.execute_batch("SAVEPOINT primitive_block")?;
+
+                    match process_primitive_block(&primitive_block, position.index, config, &mut stmts, &mut state) {
+                        Ok(()) => {
+                            conn.execute_batch("RELEASE primitive_block")?;
+                            state.release_block(&mut stmts);
                         }
-                        Block::Unknown(unknown_block) => {
-                            println!("Skipping unknown block of size {}", unknown_block.len());
+                        Err(error) if is_data_error(&error) => {
+                            conn.execute_batch("ROLLBACK TO primitive_block; RELEASE primitive_block")?;
+                            state.roll_back_block(&mut stmts);
+                            handle_block_error(position, &error.to_string(), config, &mut stmts, &mut state)?;
                         }
-                    },
-                    Err(error) => println!("Error during parsing a block: {error:?}"),
-                },
-                Err(error) => println!("Error during reading the next blob: {error:?}"),
+                        Err(error) => return Err(error),
+                    }
+                }
+                Ok(DecodedBlock::Unknown(size)) => println!("Skipping unknown block of size {size}"),
+                Err(BlockError::Parse(error)) => handle_block_error(
+                    position,
+                    &format!("Error during parsing a block: {error}"),
+                    config,
+                    &mut stmts,
+                    &mut state,
+                )?,
+                Err(BlockError::Read(error)) => handle_block_error(
+                    position,
+                    &format!("Error during reading the next blob: {error}"),
+                    config,
+                    &mut stmts,
+                    &mut state,
+                )?,
+            }
+
+            progress = ImportProgress {
+                blob_index: position.index + 1,
+                blob_offset: position.next_offset,
+                error_count: state.error_count,
+            };
+
+            if let Some(commit_every) = config.commit_every
+                && progress.blob_index.is_multiple_of(commit_every.get())
+            {
+                progress::save(conn, &progress)?;
+                conn.execute_batch("COMMIT; BEGIN")?;
+                committed = progress;
             }
+
+            Ok(())
+        },
+    );
+
+    match result {
+        Ok(()) => {}
+        Err(error) if error.is::<Interrupted>() => {
+            return match config.on_interrupt {
+                InterruptPolicy::Commit => {
+                    println!("Committing the import up to blob {}", progress.blob_index);
+
+                    if config.commit_every.is_some() {
+                        progress::save(conn, &progress)?;
+                    }
+
+                    if config.geopackage {
+                        state.feature_extents.write(conn)?;
+                    }
+
+                    conn.execute_batch("COMMIT")?;
+                    Ok(ImportEnd::Interrupted(progress))
+                }
+                InterruptPolicy::Rollback => {
+                    println!("Rolling back the import to blob {}", committed.blob_index);
+                    conn.execute_batch("ROLLBACK")?;
+                    Ok(ImportEnd::Interrupted(committed))
+                }
+            };
         }
+        Err(error) => return Err(error),
+    }
 
-        drop(stmts); // Ensure `tr` is no longer used
+    if let (Some(areas), Some(node_locations)) = (&state.areas, &state.node_locations) {
+        println!("Building areas");
+        build_areas(areas, node_locations, &mut stmts, &mut state.feature_extents.areas)?;
+    }
 
-        tr.commit()?;
+    if let (Some(changesets), Some(insert_changeset)) = (&state.changesets, &mut stmts.changeset) {
+        println!("Writing changesets");
+
+        for (id, summary) in changesets.iter() {
+            let bbox = summary.bbox.as_ref();
+
+            insert_changeset.execute(params![
+                id,
+                summary.user_id,
+                summary.user,
+                summary.node_count,
+                summary.way_count,
+                summary.relation_count,
+                bbox.map(|bbox| bbox.min_lat),
+                bbox.map(|bbox| bbox.min_lon),
+                bbox.map(|bbox| bbox.max_lat),
+                bbox.map(|bbox| bbox.max_lon)
+            ])?;
+        }
     }
 
+    if config.geopackage {
+        state.feature_extents.write(conn)?;
+    }
+
+    progress.error_count = state.error_count;
+
+    if config.commit_every.is_some() {
+        progress::save(conn, &progress)?;
+    }
+
+    conn.execute_batch("COMMIT")?;
+
+    Ok(ImportEnd::Finished(progress))
+}
+
+/// Exit code of imports which have finished, but skipped or recorded errors.
+const EXIT_CODE_ERRORS: u8 = 2;
+
+/// Exit code of imports interrupted by SIGINT or SIGTERM.
+const EXIT_CODE_INTERRUPTED: u8 = 3;
+
+/// Opens the dump for applying changes, which must already exist.
+fn open_existing_dump(config: &Config) -> anyhow::Result<rusqlite::Connection> {
+    let conn = rusqlite::Connection::open_with_flags(&config.output_db, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .with_context(|| format!("Failed to open existing SQLite database `{:?}`", config.output_db))?;
+
+    if config.filter.is_some() {
+        // Filtered ways may reference nodes which aren't imported
+        conn.execute("PRAGMA foreign_keys = OFF", [])?;
+    }
+
+    Ok(conn)
+}
+
+/// Applies the changes of an OsmChange file to an existing dump, in a single transaction.
+fn apply_change_file(change_path: &Path, config: &Config) -> anyhow::Result<()> {
+    let mut conn = open_existing_dump(config)?;
+
+    println!("Reading changes from {change_path:?}");
+    let changes = change::read_change_file(change_path)?;
+
+    let tr = conn.transaction()?;
+    let stats = change::apply_changes(&tr, config, &changes)?;
+    tr.commit()?;
+
+    println!(
+        "Applied {} created, {} modified and {} deleted elements",
+        stats.created, stats.modified, stats.deleted
+    );
+
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let config_path = std::env::args().nth(1).unwrap_or("config.toml".to_string());
+enum Command {
+    Dump { resume: bool },
+    Apply(PathBuf),
+    Update(PathBuf),
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let mut args = std::env::args().skip(1).peekable();
+
+    // `apply <change file> [config]` applies an OsmChange file, `update <replication directory> [config]` applies
+    // the replication diffs following the dump's state, otherwise `[config]` creates a new dump
+    let command = match args.next_if(|arg| arg == "apply" || arg == "update").as_deref() {
+        Some("apply") => Command::Apply(PathBuf::from(
+            args.next().context("Missing path of the change file to apply")?,
+        )),
+        Some(_) => Command::Update(PathBuf::from(
+            args.next().context("Missing path of the replication directory")?,
+        )),
+        None => Command::Dump {
+            resume: args.next_if(|arg| arg == "--resume").is_some(),
+        },
+    };
+
+    let config_path = args.next().unwrap_or("config.toml".to_string());
     let config = read_config(&config_path)?;
 
+    let resume = match command {
+        Command::Dump { resume } => resume,
+        Command::Apply(change_path) => {
+            apply_change_file(&change_path, &config)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Update(replication_dir) => {
+            replication::update(&mut open_existing_dump(&config)?, &config, &replication_dir)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+    };
+
     let mut input_pbf =
         File::open(&config.input_pbf).with_context(|| format!("Failed to open input PBF `{:?}`", config.input_pbf))?;
 
-    if config.overwrite_output && config.output_db.exists() {
-        std::fs::remove_file(&config.output_db)
-            .with_context(|| format!("Failed to remove `{:?}`", config.output_db))?;
+    if let Some(header_block) = pipeline::read_header_block(&mut input_pbf)
+        .with_context(|| format!("Failed to read the header of input PBF `{:?}`", config.input_pbf))?
+    {
+        check_required_features(&header_block, &config)?;
     }
 
-    let mut conn = rusqlite::Connection::open(&config.output_db)
-        .with_context(|| format!("Failed to open output SQLite database `{:?}`", config.output_db))?;
+    input_pbf.rewind()?;
 
-    dump(&mut input_pbf, &mut conn, &config)?;
+    let (mut conn, progress) = if resume {
+        let conn = open_existing_dump(&config)?;
+        let progress = progress::read(&conn)?;
 
-    Ok(())
+        println!("Resuming the import from blob {}", progress.blob_index);
+        input_pbf.seek(SeekFrom::Start(progress.blob_offset))?;
+
+        (conn, Some(progress))
+    } else {
+        if config.overwrite_output && config.output_db.exists() {
+            std::fs::remove_file(&config.output_db)
+                .with_context(|| format!("Failed to remove `{:?}`", config.output_db))?;
+        }
+
+        let conn = rusqlite::Connection::open(&config.output_db)
+            .with_context(|| format!("Failed to open output SQLite database `{:?}`", config.output_db))?;
+
+        (conn, None)
+    };
+
+    let snapshot = snapshot::read_snapshot(&config.input_pbf, &config)?;
+    let selection = selection::select(&config.input_pbf, &config, snapshot.as_ref())?;
+
+    let error_count = match dump(&mut input_pbf, &mut conn, &config, (snapshot, selection), progress) {
+        Ok(error_count) => error_count,
+        Err(error) if error.is::<Interrupted>() => {
+            println!("{error}");
+            return Ok(ExitCode::from(EXIT_CODE_INTERRUPTED));
+        }
+        Err(error) => return Err(error),
+    };
+
+    if error_count > 0 {
+        println!("Import finished with {error_count} errors");
+        return Ok(ExitCode::from(EXIT_CODE_ERRORS));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+
+    use prost::Message;
+
+    fn config(config: &str) -> Config {
+        toml::from_str(&format!("input_pbf = \"osm.pbf\"\noutput_db = \"out.db\"\n{config}")).unwrap()
+    }
+
+    /// Encodes primitive blocks as the zlib-compressed blobs of a PBF file.
+    fn encode_pbf(blocks: &[pbf::PrimitiveBlock]) -> Vec<u8> {
+        let mut pbf = Vec::new();
+
+        for block in blocks {
+            let data = block.encode_to_vec();
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            std::io::Write::write_all(&mut encoder, &data).unwrap();
+
+            let blob = pbf::Blob {
+                raw_size: Some(data.len() as i32),
+                data: Some(pbf::blob::Data::ZlibData(encoder.finish().unwrap())),
+            }
+            .encode_to_vec();
+
+            let header = pbf::BlobHeader {
+                r#type: "OSMData".to_string(),
+                indexdata: None,
+                datasize: blob.len() as i32,
+            }
+            .encode_to_vec();
+
+            pbf.extend((header.len() as i32).to_be_bytes());
+            pbf.extend(header);
+            pbf.extend(blob);
+        }
+
+        pbf
+    }
+
+    fn import(config: &Config, blocks: &[pbf::PrimitiveBlock]) -> rusqlite::Connection {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        dump(
+            &mut encode_pbf(blocks).as_slice(),
+            &mut conn,
+            config,
+            (None, None),
+            None,
+        )
+        .unwrap();
+        conn
+    }
+
+    fn block(strings: &[&str], group: pbf::PrimitiveGroup) -> pbf::PrimitiveBlock {
+        pbf::PrimitiveBlock {
+            stringtable: pbf::StringTable {
+                s: strings.iter().map(|string| string.as_bytes().to_vec()).collect(),
+            },
+            primitivegroup: vec![group],
+            ..Default::default()
+        }
+    }
+
+    fn delta_encode(values: &[i64]) -> Vec<i64> {
+        let mut previous = 0;
+
+        values
+            .iter()
+            .map(|value| {
+                let delta = value - previous;
+                previous = *value;
+                delta
+            })
+            .collect()
+    }
+
+    /// Returns a node at the given coordinates in units of the default granularity (100 nanodegrees).
+    fn node(id: i64, (lat, lon): (i64, i64), tags: &[(u32, u32)]) -> pbf::Node {
+        pbf::Node {
+            id,
+            lat,
+            lon,
+            keys: tags.iter().map(|(key, _)| *key).collect(),
+            vals: tags.iter().map(|(_, value)| *value).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn way(id: i64, refs: &[i64], tags: &[(u32, u32)]) -> pbf::Way {
+        pbf::Way {
+            id,
+            keys: tags.iter().map(|(key, _)| *key).collect(),
+            vals: tags.iter().map(|(_, value)| *value).collect(),
+            refs: delta_encode(refs),
+            ..Default::default()
+        }
+    }
+
+    fn relation(id: i64, members: &[(pbf::relation::MemberType, i64, i32)], tags: &[(u32, u32)]) -> pbf::Relation {
+        let member_ids: Vec<i64> = members.iter().map(|(_, member_id, _)| *member_id).collect();
+
+        pbf::Relation {
+            id,
+            keys: tags.iter().map(|(key, _)| *key).collect(),
+            vals: tags.iter().map(|(_, value)| *value).collect(),
+            roles_sid: members.iter().map(|(_, _, role)| *role).collect(),
+            memids: delta_encode(&member_ids),
+            types: members.iter().map(|(member_type, _, _)| *member_type as i32).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn query<T: rusqlite::types::FromSql>(conn: &rusqlite::Connection, sql: &str) -> Vec<T> {
+        conn.prepare(sql)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn member_order() {
+        use pbf::relation::MemberType;
+
+        let strings = ["", "outer", "inner"];
+        let nodes = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                nodes: (1..=3).map(|id| node(id, (id, id), &[])).collect(),
+                ..Default::default()
+            },
+        );
+        let ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![way(10, &[3, 1, 2, 3], &[])],
+                ..Default::default()
+            },
+        );
+        let relations = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                relations: vec![relation(
+                    20,
+                    &[
+                        (MemberType::Way, 10, 2),
+                        (MemberType::Node, 2, 0),
+                        (MemberType::Way, 10, 1),
+                    ],
+                    &[],
+                )],
+                ..Default::default()
+            },
+        );
+
+        let conn = import(&config(""), &[nodes, ways, relations]);
+
+        assert_eq!(
+            query::<i64>(
+                &conn,
+                "SELECT ref_node_id FROM way_refs WHERE way_id = 10 ORDER BY sequence_id"
+            ),
+            [3, 1, 2, 3]
+        );
+        assert_eq!(
+            query::<String>(
+                &conn,
+                "SELECT coalesce(member_way_id, member_node_id) || ':' || role FROM relation_members
+                 WHERE relation_id = 20 ORDER BY sequence_id"
+            ),
+            ["10:inner", "2:", "10:outer"]
+        );
+    }
+
+    /// Returns a block of nodes 1 to 4 at the corners of a square.
+    fn square_nodes(strings: &[&str]) -> pbf::PrimitiveBlock {
+        block(
+            strings,
+            pbf::PrimitiveGroup {
+                nodes: vec![
+                    node(1, (0, 0), &[]),
+                    node(2, (0, 10), &[]),
+                    node(3, (10, 10), &[]),
+                    node(4, (10, 0), &[]),
+                ],
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn areas() {
+        let strings = ["", "building", "yes", "highway", "residential"];
+        let ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![
+                    way(10, &[1, 2, 3, 4, 1], &[(1, 2)]),
+                    way(11, &[1, 2, 3, 4, 1], &[(3, 4)]),
+                ],
+                ..Default::default()
+            },
+        );
+        // The building is collected before the invalid tag fails the block
+        let invalid_ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![way(12, &[1, 2, 3, 4, 1], &[(1, 2)]), way(13, &[1, 2], &[(99, 2)])],
+                ..Default::default()
+            },
+        );
+
+        let conn = import(
+            &config("on_error = \"skip\"\n[areas]"),
+            &[square_nodes(&strings), ways, invalid_ways],
+        );
+
+        assert_eq!(query::<i64>(&conn, "SELECT way_id FROM areas"), [10]);
+        assert!(query::<i64>(&conn, "SELECT way_id FROM area_errors").is_empty());
+    }
+
+    #[test]
+    fn rtrees() {
+        let strings = ["", "name", "Invalid"];
+        // The location of node 5 is collected before the invalid tag fails the block
+        let invalid_nodes = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                nodes: vec![node(5, (20, 20), &[]), node(6, (30, 30), &[(1, 99)])],
+                ..Default::default()
+            },
+        );
+        let ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![way(10, &[1, 2, 3], &[]), way(11, &[1, 5], &[])],
+                ..Default::default()
+            },
+        );
+
+        let conn = import(
+            &config("on_error = \"skip\"\n[nodes_rtree]\n[ways_rtree]\n[way_refs]\nskip = true"),
+            &[square_nodes(&strings), invalid_nodes, ways],
+        );
+
+        assert_eq!(
+            query::<i64>(&conn, "SELECT id FROM nodes_rtree ORDER BY id"),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            query::<String>(
+                &conn,
+                "SELECT id || ':' || min_lat || ',' || max_lat || ',' || min_lon || ',' || max_lon FROM ways_rtree"
+            ),
+            ["10:0.0,1000.0,0.0,1000.0"]
+        );
+    }
+
+    #[test]
+    fn geopackage_extents() {
+        let strings = ["", "name", "Point", "Line"];
+        let nodes = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                nodes: vec![
+                    node(1, (0, 0), &[]),
+                    node(2, (0, 10), &[(1, 2)]),
+                    node(3, (10, 30), &[]),
+                    node(4, (20, 0), &[(1, 2)]),
+                ],
+                ..Default::default()
+            },
+        );
+        let ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![way(10, &[1, 2, 3], &[(1, 3)])],
+                ..Default::default()
+            },
+        );
+
+        let conn = import(&config("geopackage = true"), &[nodes, ways]);
+
+        assert_eq!(
+            query::<String>(
+                &conn,
+                "SELECT table_name || ':' || (min_x * 1e9) || ',' || (min_y * 1e9) || ',' || (max_x * 1e9) || ',' \
+                 || (max_y * 1e9) FROM gpkg_contents ORDER BY table_name"
+            ),
+            [
+                "node_features:0.0,0.0,1000.0,2000.0",
+                "way_features:0.0,0.0,3000.0,1000.0"
+            ]
+        );
+        assert_eq!(
+            query::<String>(
+                &conn,
+                "SELECT name FROM sqlite_master WHERE name LIKE '%_features' AND sql LIKE '%AUTOINCREMENT%' \
+                 ORDER BY name"
+            ),
+            ["node_features", "way_features"]
+        );
+    }
+
+    #[test]
+    fn required_features() {
+        let header = |features: &[&str]| pbf::HeaderBlock {
+            required_features: features.iter().map(|feature| feature.to_string()).collect(),
+            ..Default::default()
+        };
+
+        let regular = config("");
+        check_required_features(&header(&["OsmSchema-V0.6", "DenseNodes"]), &regular).unwrap();
+        assert!(check_required_features(&header(&["OsmSchema-V0.6", "Sort.Type_then_ID"]), &regular).is_err());
+
+        // Full-history inputs aren't imported in history mode implicitly, which `apply` and `update` wouldn't know of
+        let full_history = header(&["OsmSchema-V0.6", "HistoricalInformation"]);
+        assert!(check_required_features(&full_history, &regular).is_err());
+        check_required_features(&full_history, &config("history = true")).unwrap();
+        check_required_features(&full_history, &config("snapshot_at = \"2020-01-01T00:00:00Z\"")).unwrap();
+    }
+
+    #[test]
+    fn missing_history_version() {
+        let versioned = |id, version| pbf::Node {
+            info: Some(pbf::Info {
+                version,
+                ..Default::default()
+            }),
+            ..node(id, (0, 0), &[])
+        };
+
+        let blocks = [
+            block(
+                &[""],
+                pbf::PrimitiveGroup {
+                    nodes: vec![versioned(1, Some(1)), versioned(2, None)],
+                    ..Default::default()
+                },
+            ),
+            block(
+                &[""],
+                pbf::PrimitiveGroup {
+                    nodes: vec![versioned(3, Some(1))],
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let error_count = dump(
+            &mut encode_pbf(&blocks).as_slice(),
+            &mut conn,
+            &config("history = true\non_error = \"record\""),
+            (None, None),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(error_count, 1);
+        assert_eq!(query::<i64>(&conn, "SELECT id FROM nodes"), [3]);
+        assert_eq!(
+            query::<String>(&conn, "SELECT error FROM import_errors"),
+            ["Invalid node 2 in block 0: missing version"]
+        );
+    }
+
+    #[test]
+    fn missing_snapshot_timestamp() {
+        let stamped = |id, timestamp| pbf::Node {
+            info: Some(pbf::Info {
+                version: Some(1),
+                timestamp,
+                ..Default::default()
+            }),
+            ..node(id, (0, 0), &[])
+        };
+
+        let pbf = encode_pbf(&[block(
+            &[""],
+            pbf::PrimitiveGroup {
+                nodes: vec![stamped(1, Some(1000)), stamped(2, None)],
+                ..Default::default()
+            },
+        )]);
+
+        let input_path =
+            std::env::temp_dir().join(format!("missing_snapshot_timestamp_{}.osm.pbf", std::process::id()));
+        std::fs::write(&input_path, &pbf).unwrap();
+
+        let config = config("snapshot_at = \"2020-01-01T00:00:00Z\"\non_error = \"fail\"");
+        let snapshot = snapshot::read_snapshot(&input_path, &config);
+        std::fs::remove_file(&input_path).unwrap();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let error = dump(&mut pbf.as_slice(), &mut conn, &config, (snapshot.unwrap(), None), None).unwrap_err();
+
+        assert!(
+            error
+                .to_string()
+                .contains("Invalid node 2 in block 0: missing timestamp")
+        );
+    }
+
+    #[test]
+    fn changesets_of_rolled_back_blocks() {
+        let in_changeset = |id, tags: &[(u32, u32)]| pbf::Node {
+            info: Some(pbf::Info {
+                changeset: Some(1),
+                ..Default::default()
+            }),
+            ..node(id, (id, id), tags)
+        };
+
+        let strings = ["", "name", "Invalid"];
+        let blocks = [
+            block(
+                &strings,
+                pbf::PrimitiveGroup {
+                    nodes: vec![in_changeset(1, &[]), in_changeset(2, &[])],
+                    ..Default::default()
+                },
+            ),
+            // Node 3 is added to the changeset before the invalid tag fails the block
+            block(
+                &strings,
+                pbf::PrimitiveGroup {
+                    nodes: vec![in_changeset(3, &[]), in_changeset(4, &[(1, 99)])],
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let conn = import(&config("on_error = \"skip\"\n[changesets]"), &blocks);
+
+        assert_eq!(query::<i64>(&conn, "SELECT id FROM nodes"), [1, 2]);
+        assert_eq!(
+            query::<String>(
+                &conn,
+                "SELECT node_count || ':' || max_lat FROM changesets WHERE id = 1"
+            ),
+            ["2:200"]
+        );
+    }
+
+    #[test]
+    fn normalized_tags_of_rolled_back_blocks() {
+        let strings = ["", "building", "yes", "highway", "residential"];
+        // The tag of way 12 is added to the dictionary before the invalid tag fails the block
+        let invalid_ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![way(12, &[1, 2], &[(1, 2)]), way(13, &[1, 2], &[(99, 2)])],
+                ..Default::default()
+            },
+        );
+        let ways = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                ways: vec![way(10, &[1, 2], &[(3, 4)]), way(11, &[2, 3], &[(1, 2)])],
+                ..Default::default()
+            },
+        );
+
+        let conn = import(
+            &config("on_error = \"skip\"\nnormalize_tags = true"),
+            &[square_nodes(&strings), invalid_ways, ways],
+        );
+
+        assert_eq!(
+            query::<String>(
+                &conn,
+                "SELECT way_id || ':' || key || '=' || value FROM way_tags_with_strings ORDER BY way_id"
+            ),
+            ["10:highway=residential", "11:building=yes"]
+        );
+    }
+
+    #[test]
+    fn pragmas_of_failed_imports() {
+        let strings = ["", "name"];
+        let invalid_nodes = block(
+            &strings,
+            pbf::PrimitiveGroup {
+                nodes: vec![node(1, (0, 0), &[(1, 99)])],
+                ..Default::default()
+            },
+        );
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let synchronous = |conn: &rusqlite::Connection| -> i64 {
+            conn.pragma_query_value(None, "synchronous", |row| row.get(0)).unwrap()
+        };
+        let previous_synchronous = synchronous(&conn);
+
+        let result = dump(
+            &mut encode_pbf(&[invalid_nodes]).as_slice(),
+            &mut conn,
+            &config("on_error = \"fail\"\n[sqlite]\npreset = \"fast\""),
+            (None, None),
+            None,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(synchronous(&conn), previous_synchronous);
+    }
 }
@@ -0,0 +1,257 @@
+use rosm_pbf_reader::{Block, BlockParser, Error, RawBlock, pbf, read_blob};
+
+use std::collections::HashMap;
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+
+/// A parsed block, owning all of its data so it can be sent between threads.
+pub enum DecodedBlock {
+    Header(pbf::HeaderBlock),
+    Primitive(pbf::PrimitiveBlock),
+    Unknown(usize),
+}
+
+pub enum BlockError {
+    Read(Error),
+    Parse(Error),
+}
+
+pub type BlockResult = Result<DecodedBlock, BlockError>;
+
+/// Position of a blob in the input.
+#[derive(Clone, Copy, Debug)]
+pub struct BlobPosition {
+    pub index: usize,
+    /// Offset of the blob's header in bytes.
+    pub offset: u64,
+    /// Offset following the blob in bytes, where the next one starts.
+    pub next_offset: u64,
+}
+
+/// Reader keeping track of the number of bytes read.
+struct CountingReader<'a, Input> {
+    input: &'a mut Input,
+    offset: u64,
+}
+
+impl<Input: std::io::Read> std::io::Read for CountingReader<'_, Input> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+fn decode_block(block_parser: &mut BlockParser, raw_block: RawBlock) -> Result<DecodedBlock, Error> {
+    let block = match block_parser.parse_block(raw_block)? {
+        Block::Header(header_block) => DecodedBlock::Header(header_block),
+        Block::Primitive(primitive_block) => DecodedBlock::Primitive(primitive_block),
+        Block::Unknown(unknown_block) => DecodedBlock::Unknown(unknown_block.len()),
+    };
+
+    Ok(block)
+}
+
+/// Reads the first blob of `input`, and returns it if it's a header block.
+pub fn read_header_block<Input: std::io::Read>(input: &mut Input) -> Result<Option<pbf::HeaderBlock>, Error> {
+    let Some(raw_block) = read_blob(input) else {
+        return Ok(None);
+    };
+
+    match BlockParser::default().parse_block(raw_block?)? {
+        Block::Header(header_block) => Ok(Some(header_block)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the maximum number of blobs which may be read but not yet consumed, bounding the memory used by decoded
+/// blocks waiting for a slow one before them.
+fn max_blobs_in_flight(worker_count: usize) -> usize {
+    worker_count * 4
+}
+
+/// Reads blobs from `input` and decodes them on `worker_count` threads.
+///
+/// Decoded blocks are handed to `consume` on the calling thread with their positions, in the same order as they appear
+/// in the file. If `consume` returns an error, reading stops and the error is returned.
+pub fn decode_blocks<Input, Consume>(input: &mut Input, worker_count: usize, consume: Consume) -> anyhow::Result<()>
+where
+    Input: std::io::Read + Send,
+    Consume: FnMut(BlobPosition, BlockResult) -> anyhow::Result<()>,
+{
+    decode_blocks_from(input, (0, 0), worker_count, consume)
+}
+
+/// Like `decode_blocks`, but for an input positioned at the start of the blob with the given index and offset, e.g.
+/// for resuming an import.
+pub fn decode_blocks_from<Input, Consume>(
+    input: &mut Input,
+    (first_index, first_offset): (usize, u64),
+    worker_count: usize,
+    mut consume: Consume,
+) -> anyhow::Result<()>
+where
+    Input: std::io::Read + Send,
+    Consume: FnMut(BlobPosition, BlockResult) -> anyhow::Result<()>,
+{
+    let worker_count = worker_count.max(1);
+
+    std::thread::scope(|scope| {
+        let (raw_sender, raw_receiver) = sync_channel::<(BlobPosition, Result<RawBlock, Error>)>(worker_count * 2);
+        let (decoded_sender, decoded_receiver) = sync_channel::<(BlobPosition, BlockResult)>(worker_count * 2);
+        // The reader takes a permit for each blob, which is returned once the blob has been consumed
+        let (permit_sender, permit_receiver) = sync_channel::<()>(max_blobs_in_flight(worker_count));
+
+        scope.spawn(move || {
+            let mut input = CountingReader {
+                input,
+                offset: first_offset,
+            };
+            let mut index = first_index;
+
+            loop {
+                let offset = input.offset;
+
+                let Some(result) = read_blob(&mut input) else {
+                    break;
+                };
+
+                let position = BlobPosition {
+                    index,
+                    offset,
+                    next_offset: input.offset,
+                };
+
+                if permit_sender.send(()).is_err() || raw_sender.send((position, result)).is_err() {
+                    break; // The consumer or all workers have stopped
+                }
+                index += 1;
+            }
+        });
+
+        let raw_receiver = Arc::new(Mutex::new(raw_receiver));
+
+        for _ in 0..worker_count {
+            let raw_receiver = Arc::clone(&raw_receiver);
+            let decoded_sender = decoded_sender.clone();
+
+            scope.spawn(move || {
+                let mut block_parser = BlockParser::default();
+
+                loop {
+                    let received = raw_receiver.lock().expect("poisoned raw block receiver").recv();
+
+                    let Ok((position, result)) = received else {
+                        break; // The reader has finished
+                    };
+
+                    let decoded = match result {
+                        Ok(raw_block) => decode_block(&mut block_parser, raw_block).map_err(BlockError::Parse),
+                        Err(error) => Err(BlockError::Read(error)),
+                    };
+
+                    if decoded_sender.send((position, decoded)).is_err() {
+                        break; // The consumer has stopped
+                    }
+                }
+            });
+        }
+
+        // Only the workers may keep these alive, otherwise the threads couldn't notice when the other side stops
+        drop(raw_receiver);
+        drop(decoded_sender);
+
+        let mut next_index = first_index;
+        let mut pending = HashMap::new();
+
+        for (position, result) in decoded_receiver {
+            pending.insert(position.index, (position, result));
+
+            while let Some((position, result)) = pending.remove(&next_index) {
+                consume(position, result)?;
+                next_index += 1;
+
+                permit_receiver.recv()?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod pipeline_tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn read_error_position() {
+        // Blob header size of 3 bytes, followed by an invalid blob header
+        let mut input: &[u8] = &[0, 0, 0, 3, 0xFF, 0xFF, 0xFF];
+        let mut positions = Vec::new();
+
+        decode_blocks(&mut input, 2, |position, result| {
+            assert!(matches!(result, Err(BlockError::Read(_))));
+            positions.push((position.index, position.offset));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(positions, [(0, 0)]);
+    }
+
+    #[test]
+    fn resumed_positions() {
+        let mut input: &[u8] = &[0, 0, 0, 3, 0xFF, 0xFF, 0xFF];
+        let mut positions = Vec::new();
+
+        decode_blocks_from(&mut input, (5, 100), 2, |position, _| {
+            positions.push((position.index, position.offset, position.next_offset));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(positions, [(5, 100, 107)]);
+    }
+
+    /// Input of invalid blobs of 7 bytes each, counting the bytes read.
+    struct InvalidBlobs {
+        data: Vec<u8>,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl std::io::Read for InvalidBlobs {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let offset = self.read.load(Ordering::SeqCst);
+            let length = (self.data.len() - offset).min(buf.len());
+
+            buf[..length].copy_from_slice(&self.data[offset..offset + length]);
+            self.read.fetch_add(length, Ordering::SeqCst);
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn blobs_in_flight() {
+        let read = Arc::new(AtomicUsize::new(0));
+        let mut input = InvalidBlobs {
+            data: [0, 0, 0, 3, 0xFF, 0xFF, 0xFF].repeat(100),
+            read: Arc::clone(&read),
+        };
+        let mut indices = Vec::new();
+
+        decode_blocks(&mut input, 1, |position, _| {
+            // A slow consumer doesn't let the reader get further ahead than the limit
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            let read_blobs = read.load(Ordering::SeqCst).div_ceil(7);
+            assert!(read_blobs <= position.index + 1 + max_blobs_in_flight(1));
+
+            indices.push(position.index);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(indices, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, bail};
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// Progress of an import committed periodically, stored in the `import_progress` table so an interrupted import can be
+/// resumed.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ImportProgress {
+    /// Index of the first blob which hasn't been imported yet.
+    pub blob_index: usize,
+    /// Offset of the first blob which hasn't been imported yet in bytes.
+    pub blob_offset: u64,
+    /// Number of errors skipped or recorded so far.
+    pub error_count: usize,
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE import_progress (
+            blob_index INTEGER NOT NULL,
+            blob_offset INTEGER NOT NULL,
+            error_count INTEGER NOT NULL,
+            finished BOOL NOT NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "INSERT INTO import_progress (blob_index, blob_offset, error_count, finished) VALUES (0, 0, 0, FALSE)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Updates the stored progress, which is committed along with the imported data.
+pub fn save(conn: &Connection, progress: &ImportProgress) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE import_progress SET blob_index = ?1, blob_offset = ?2, error_count = ?3",
+        params![
+            progress.blob_index as i64,
+            progress.blob_offset as i64,
+            progress.error_count as i64
+        ],
+    )?;
+    Ok(())
+}
+
+/// Marks the import as finished, after which it can't be resumed.
+pub fn finish(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute("UPDATE import_progress SET finished = TRUE", [])?;
+    Ok(())
+}
+
+pub fn create_metadata_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE import_metadata (
+            key TEXT PRIMARY KEY,
+            value
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Records in the `import_metadata` table whether the import has finished or been interrupted, and how far it got.
+pub fn write_metadata(conn: &Connection, progress: &ImportProgress, finished: bool) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("INSERT OR REPLACE INTO import_metadata (key, value) VALUES (?1, ?2)")?;
+
+    stmt.execute(params!["status", if finished { "finished" } else { "interrupted" }])?;
+    stmt.execute(params!["blob_index", progress.blob_index as i64])?;
+    stmt.execute(params!["blob_offset", progress.blob_offset as i64])?;
+    stmt.execute(params!["error_count", progress.error_count as i64])?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO import_metadata (key, value) VALUES ('ended_at', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Reads the progress of an unfinished import to resume.
+pub fn read(conn: &Connection) -> anyhow::Result<ImportProgress> {
+    let progress = conn
+        .query_row(
+            "SELECT blob_index, blob_offset, error_count, finished FROM import_progress",
+            [],
+            |row| {
+                let progress = ImportProgress {
+                    blob_index: row.get::<_, i64>(0)? as usize,
+                    blob_offset: row.get::<_, i64>(1)? as u64,
+                    error_count: row.get::<_, i64>(2)? as usize,
+                };
+                Ok((progress, row.get::<_, bool>(3)?))
+            },
+        )
+        .optional()
+        .context("The output has no import progress to resume, as `commit_every` wasn't set when creating it")?;
+
+    match progress {
+        Some((_, true)) => bail!("The import has already finished"),
+        Some((progress, false)) => Ok(progress),
+        None => bail!("The output has no import progress to resume"),
+    }
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn save_and_read() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        assert!(read(&conn).is_err());
+
+        create_table(&conn).unwrap();
+        assert_eq!(read(&conn).unwrap(), ImportProgress::default());
+
+        let progress = ImportProgress {
+            blob_index: 12,
+            blob_offset: 345_678,
+            error_count: 1,
+        };
+
+        save(&conn, &progress).unwrap();
+        assert_eq!(read(&conn).unwrap(), progress);
+
+        finish(&conn).unwrap();
+        assert!(read(&conn).is_err());
+    }
+
+    #[test]
+    fn metadata() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_metadata_table(&conn).unwrap();
+
+        let status = |conn: &Connection| -> (String, i64) {
+            conn.query_row(
+                "SELECT
+                    (SELECT value FROM import_metadata WHERE key = 'status'),
+                    (SELECT value FROM import_metadata WHERE key = 'blob_index')",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+
+        let mut progress = ImportProgress {
+            blob_index: 3,
+            blob_offset: 4_567,
+            error_count: 0,
+        };
+
+        write_metadata(&conn, &progress, false).unwrap();
+        assert_eq!(status(&conn), ("interrupted".to_string(), 3));
+
+        progress.blob_index = 8;
+        write_metadata(&conn, &progress, true).unwrap();
+        assert_eq!(status(&conn), ("finished".to_string(), 8));
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM import_metadata", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 5);
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, bail};
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::change::{self, parse_timestamp};
+use super::config::Config;
+
+use std::path::{Path, PathBuf};
+
+/// Contents of a replication `state.txt` file.
+#[derive(Debug, PartialEq)]
+pub struct State {
+    pub sequence_number: i64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<i64>,
+}
+
+/// Parses a replication state file, which is a Java properties file with `sequenceNumber` and `timestamp` keys.
+pub fn parse_state(contents: &str) -> anyhow::Result<State> {
+    let mut sequence_number = None;
+    let mut timestamp = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("Invalid line `{line}`");
+        };
+
+        let value = value.replace('\\', "");
+
+        match key.trim() {
+            "sequenceNumber" => {
+                sequence_number = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid sequence number `{value}`"))?,
+                )
+            }
+            "timestamp" => timestamp = Some(parse_timestamp(value.trim())? / 1000),
+            _ => {}
+        }
+    }
+
+    Ok(State {
+        sequence_number: sequence_number.context("Missing sequence number")?,
+        timestamp,
+    })
+}
+
+/// Returns the path of a replication file without its extension, like `000/001/234` for sequence number 1234.
+fn sequence_path(replication_dir: &Path, sequence_number: i64) -> PathBuf {
+    replication_dir
+        .join(format!("{:03}", sequence_number / 1_000_000))
+        .join(format!("{:03}", sequence_number / 1000 % 1000))
+        .join(format!("{:03}", sequence_number % 1000))
+}
+
+fn change_file(replication_dir: &Path, sequence_number: i64) -> Option<PathBuf> {
+    let path = sequence_path(replication_dir, sequence_number);
+
+    ["osc.gz", "osc"]
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|path| path.exists())
+}
+
+fn read_state_file(replication_dir: &Path, sequence_number: i64) -> anyhow::Result<Option<State>> {
+    let path = sequence_path(replication_dir, sequence_number).with_extension("state.txt");
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read replication state `{path:?}`"))?;
+    let state = parse_state(&contents).with_context(|| format!("Failed to parse replication state `{path:?}`"))?;
+
+    if state.sequence_number != sequence_number {
+        bail!(
+            "Replication state `{path:?}` has sequence number {}, expected {sequence_number}",
+            state.sequence_number
+        );
+    }
+
+    Ok(Some(state))
+}
+
+/// Applies the diffs of a replication directory following the dump's replication state, in order of their sequence
+/// numbers, until the first missing one. Each diff is applied in its own transaction, along with advancing the state.
+pub fn update(conn: &mut Connection, config: &Config, replication_dir: &Path) -> anyhow::Result<()> {
+    if config.replication_state.skip {
+        bail!("Updating requires the `replication_state` table");
+    }
+
+    let mut sequence_number: i64 = conn
+        .query_row("SELECT sequence_number FROM replication_state", [], |row| row.get(0))
+        .optional()?
+        .context("The dump has no replication state, as its input had no replication sequence number")?;
+
+    println!("Replication sequence number of the dump is {sequence_number}");
+
+    while let Some(change_path) = change_file(replication_dir, sequence_number + 1) {
+        sequence_number += 1;
+
+        let changes = change::read_change_file(&change_path)?;
+        let state = read_state_file(replication_dir, sequence_number)?;
+
+        let tr = conn.transaction()?;
+        let stats = change::apply_changes(&tr, config, &changes)?;
+
+        tr.execute(
+            "UPDATE replication_state SET sequence_number = ?1, timestamp = coalesce(?2, timestamp)",
+            params![sequence_number, state.and_then(|state| state.timestamp)],
+        )?;
+
+        tr.commit()?;
+
+        println!(
+            "Applied sequence number {sequence_number}: {} created, {} modified and {} deleted elements",
+            stats.created, stats.modified, stats.deleted
+        );
+    }
+
+    println!("Replication sequence number of the dump is {sequence_number}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod replication_tests {
+    use super::*;
+
+    #[test]
+    fn state() {
+        let state = parse_state(
+            "#Wed Jan 31 12:00:02 UTC 2024
+sequenceNumber=1234
+timestamp=2024-01-31T12\\:00\\:00Z
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            state,
+            State {
+                sequence_number: 1234,
+                timestamp: Some(1_706_702_400)
+            }
+        );
+
+        assert!(parse_state("timestamp=2024-01-31T12\\:00\\:00Z").is_err());
+        assert!(parse_state("sequenceNumber=x").is_err());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            sequence_path(Path::new("minute"), 6_012_345),
+            Path::new("minute/006/012/345")
+        );
+        assert_eq!(
+            sequence_path(Path::new("minute"), 42).with_extension("state.txt"),
+            Path::new("minute/000/000/042.state.txt")
+        );
+    }
+}
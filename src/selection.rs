@@ -0,0 +1,283 @@
+use rosm_pbf_reader::DeltaValueReader;
+use rosm_pbf_reader::dense::{DenseNodeReader, new_dense_tag_reader};
+use rosm_pbf_reader::util::normalize_coord;
+use rosm_pbf_reader::{new_tag_reader, pbf};
+
+use super::OsmPrimitive;
+use super::clip::Region;
+use super::config::{ClipStrategy, Config};
+use super::error::is_data_error;
+use super::filter::{ElementType, TagFilter, read_tags};
+use super::pipeline::{self, DecodedBlock};
+use super::snapshot::Snapshot;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+
+/// IDs of the elements to import, determined by reading the input before the import.
+#[derive(Default)]
+pub struct Selection {
+    pub nodes: HashSet<i64>,
+    pub ways: HashSet<i64>,
+    pub relations: HashSet<i64>,
+}
+
+impl Selection {
+    pub fn contains(&self, element_type: ElementType, id: i64) -> bool {
+        match element_type {
+            ElementType::Node => self.nodes.contains(&id),
+            ElementType::Way => self.ways.contains(&id),
+            ElementType::Relation => self.relations.contains(&id),
+        }
+    }
+}
+
+struct Selector<'a> {
+    snapshot: Option<&'a Snapshot>,
+    region: Option<Region>,
+    filter: Option<&'a TagFilter>,
+    complete_ways: bool,
+    add_dependencies: bool,
+
+    /// Elements inside the clipping region (if there's one).
+    in_region: Selection,
+    /// Elements inside the clipping region, matching the filter (if there's one).
+    selection: Selection,
+    /// Elements referenced by selected ways and relations.
+    dependencies: Selection,
+}
+
+impl Selector<'_> {
+    fn matches(&self, element_type: ElementType, tags: &[(&str, &str)]) -> bool {
+        self.filter.is_none_or(|filter| filter.matches(element_type, tags))
+    }
+
+    /// Returns whether the element is part of the snapshot (if there's one), otherwise it's ignored.
+    fn in_snapshot<P: OsmPrimitive>(&self, element_type: ElementType, primitive: &P) -> bool {
+        self.snapshot
+            .is_none_or(|snapshot| snapshot.contains(element_type, primitive))
+    }
+
+    fn is_way_in_region(&self, way_id: i64) -> bool {
+        self.region.is_none() || self.in_region.ways.contains(&way_id)
+    }
+
+    fn process_node(&mut self, id: i64, coord: (i64, i64), tags: &[(&str, &str)]) {
+        let in_region = match &self.region {
+            Some(region) => region.contains(coord),
+            None => true,
+        };
+
+        if in_region && self.region.is_some() {
+            self.in_region.nodes.insert(id);
+        }
+
+        if in_region && self.matches(ElementType::Node, tags) {
+            self.selection.nodes.insert(id);
+        }
+    }
+
+    fn process_primitive_block(&mut self, block: &pbf::PrimitiveBlock) -> anyhow::Result<()> {
+        let string_table = &block.stringtable;
+
+        for group in &block.primitivegroup {
+            if let Some(dense_nodes) = &group.dense {
+                for node in DenseNodeReader::new(dense_nodes)? {
+                    let node = node?;
+
+                    if !self.in_snapshot(ElementType::Node, &node) {
+                        continue;
+                    }
+
+                    let tags = read_tags(new_dense_tag_reader(string_table, node.key_value_indices))?;
+
+                    self.process_node(node.id, normalize_coord(node.lat, node.lon, block), &tags);
+                }
+            }
+
+            for node in &group.nodes {
+                if !self.in_snapshot(ElementType::Node, node) {
+                    continue;
+                }
+
+                let tags = read_tags(new_tag_reader(string_table, &node.keys, &node.vals))?;
+
+                self.process_node(node.id, normalize_coord(node.lat, node.lon, block), &tags);
+            }
+
+            for way in &group.ways {
+                if !self.in_snapshot(ElementType::Way, way) {
+                    continue;
+                }
+
+                let in_region = self.region.is_none()
+                    || DeltaValueReader::new(&way.refs).any(|node_id| self.in_region.nodes.contains(&node_id));
+
+                if !in_region {
+                    continue;
+                }
+
+                if self.region.is_some() {
+                    self.in_region.ways.insert(way.id);
+                }
+
+                let tags = read_tags(new_tag_reader(string_table, &way.keys, &way.vals))?;
+
+                if self.matches(ElementType::Way, &tags) {
+                    self.selection.ways.insert(way.id);
+
+                    if self.complete_ways || self.add_dependencies {
+                        self.dependencies.nodes.extend(DeltaValueReader::new(&way.refs));
+                    }
+                }
+            }
+
+            for relation in &group.relations {
+                use pbf::relation::MemberType;
+
+                if !self.in_snapshot(ElementType::Relation, relation) {
+                    continue;
+                }
+
+                let members: Vec<(MemberType, i64)> = DeltaValueReader::new(&relation.memids)
+                    .zip(&relation.types)
+                    .filter_map(|(member_id, member_type)| Some((MemberType::try_from(*member_type).ok()?, member_id)))
+                    .collect();
+
+                let in_region = self.region.is_none()
+                    || members.iter().any(|(member_type, member_id)| match member_type {
+                        MemberType::Node => self.in_region.nodes.contains(member_id),
+                        MemberType::Way => self.in_region.ways.contains(member_id),
+                        MemberType::Relation => self.in_region.relations.contains(member_id),
+                    });
+
+                if !in_region {
+                    continue;
+                }
+
+                if self.region.is_some() {
+                    self.in_region.relations.insert(relation.id);
+                }
+
+                let tags = read_tags(new_tag_reader(string_table, &relation.keys, &relation.vals))?;
+
+                if self.matches(ElementType::Relation, &tags) {
+                    self.selection.relations.insert(relation.id);
+
+                    if self.add_dependencies {
+                        for (member_type, member_id) in members {
+                            match member_type {
+                                MemberType::Node => self.dependencies.nodes.insert(member_id),
+                                MemberType::Way => self.dependencies.ways.insert(member_id),
+                                MemberType::Relation => self.dependencies.relations.insert(member_id),
+                            };
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the nodes of ways which aren't selected themselves, but are members of selected relations.
+    fn process_dependency_ways(&mut self, block: &pbf::PrimitiveBlock) {
+        for group in &block.primitivegroup {
+            for way in &group.ways {
+                if self.in_snapshot(ElementType::Way, way)
+                    && self.dependencies.ways.contains(&way.id)
+                    && !self.selection.ways.contains(&way.id)
+                    && self.is_way_in_region(way.id)
+                {
+                    self.dependencies.nodes.extend(DeltaValueReader::new(&way.refs));
+                }
+            }
+        }
+    }
+
+    fn into_selection(self) -> Selection {
+        let mut selection = self.selection;
+
+        // Dependencies outside of the clipping region are left out, except for nodes of complete ways
+        let in_region = |ids: &HashSet<i64>, id: &i64| self.region.is_none() || ids.contains(id);
+
+        selection.nodes.extend(
+            self.dependencies
+                .nodes
+                .into_iter()
+                .filter(|id| self.complete_ways || in_region(&self.in_region.nodes, id)),
+        );
+        selection.ways.extend(
+            self.dependencies
+                .ways
+                .into_iter()
+                .filter(|id| in_region(&self.in_region.ways, id)),
+        );
+        selection.relations.extend(
+            self.dependencies
+                .relations
+                .into_iter()
+                .filter(|id| in_region(&self.in_region.relations, id)),
+        );
+
+        selection
+    }
+}
+
+/// Reads the input and selects the elements to import according to the clipping and filter configuration. Returns
+/// `None` if there's nothing to select in advance: either all elements are imported, or the filter is evaluated
+/// during the import.
+///
+/// Elements are selected if they're inside the clipping region and match the filter. Nodes are inside the region if
+/// their coordinates are, ways and relations if they reference any node, way or (preceding) relation inside it.
+///
+/// If the filter's `add_dependencies` is set, elements referenced by selected elements are selected too: nodes of
+/// ways, and members of relations (with the nodes of member ways). In this case the input may be read twice. With
+/// [`ClipStrategy::CompleteWays`], all nodes of selected ways are selected, even outside of the region.
+///
+/// If a `snapshot` is given, only the elements' versions in it are considered.
+pub fn select(input_path: &Path, config: &Config, snapshot: Option<&Snapshot>) -> anyhow::Result<Option<Selection>> {
+    let add_dependencies = config.filter.as_ref().is_some_and(|filter| filter.add_dependencies);
+
+    if config.clip.is_none() && !add_dependencies {
+        return Ok(None);
+    }
+
+    println!("Selecting elements to import");
+
+    let mut selector = Selector {
+        snapshot,
+        region: config.clip.as_ref().map(Region::from_config).transpose()?,
+        filter: config.filter.as_ref().map(|filter| &filter.expressions),
+        complete_ways: config
+            .clip
+            .as_ref()
+            .is_some_and(|clip| matches!(clip.strategy, ClipStrategy::CompleteWays)),
+        add_dependencies,
+        in_region: Selection::default(),
+        selection: Selection::default(),
+        dependencies: Selection::default(),
+    };
+
+    pipeline::decode_blocks(&mut File::open(input_path)?, config.worker_threads, |_, result| {
+        if let Ok(DecodedBlock::Primitive(primitive_block)) = result
+            && let Err(error) = selector.process_primitive_block(&primitive_block)
+            && !is_data_error(&error)
+        {
+            return Err(error);
+        }
+        Ok(()) // Errors of the input are reported during the import
+    })?;
+
+    if !selector.dependencies.ways.is_subset(&selector.selection.ways) {
+        pipeline::decode_blocks(&mut File::open(input_path)?, config.worker_threads, |_, result| {
+            if let Ok(DecodedBlock::Primitive(primitive_block)) = result {
+                selector.process_dependency_ways(&primitive_block);
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(Some(selector.into_selection()))
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, bail};
+use rosm_pbf_reader::dense::DenseNodeReader;
+use rosm_pbf_reader::pbf;
+use rosm_pbf_reader::util::normalize_timestamp;
+
+use super::OsmPrimitive;
+use super::change::parse_timestamp;
+use super::config::Config;
+use super::error::{ElementError, ElementErrorKind, is_data_error};
+use super::filter::ElementType;
+use super::pipeline::{self, DecodedBlock};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Versions of elements as they were at a given time, determined by reading a full-history input before the import.
+#[derive(Default)]
+pub struct Snapshot {
+    timestamp: i64,
+    nodes: HashMap<i64, (i32, bool)>,
+    ways: HashMap<i64, (i32, bool)>,
+    relations: HashMap<i64, (i32, bool)>,
+}
+
+impl Snapshot {
+    fn versions(&self, element_type: ElementType) -> &HashMap<i64, (i32, bool)> {
+        match element_type {
+            ElementType::Node => &self.nodes,
+            ElementType::Way => &self.ways,
+            ElementType::Relation => &self.relations,
+        }
+    }
+
+    /// Returns whether `primitive` is the version of the element at the snapshot's time.
+    pub fn contains<P: OsmPrimitive>(&self, element_type: ElementType, primitive: &P) -> bool {
+        let version = primitive.info().and_then(|info| info.version);
+
+        match (version, self.versions(element_type).get(&primitive.id())) {
+            (Some(version), Some((snapshot_version, _))) => version == *snapshot_version,
+            _ => false,
+        }
+    }
+
+    /// Returns an error if `primitive` lacks the version or timestamp needed to place it in the snapshot.
+    pub fn check<P: OsmPrimitive>(&self, primitive: &P, blob_index: usize) -> Result<(), ElementError> {
+        let info = primitive.info();
+        let error = |kind| ElementError::new(primitive, blob_index, kind);
+
+        match (info.and_then(|info| info.version), info.and_then(|info| info.timestamp)) {
+            (None, _) => Err(error(ElementErrorKind::MissingVersion)),
+            (_, None) => Err(error(ElementErrorKind::MissingTimestamp)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records `primitive` if it's the newest version so far not newer than the snapshot.
+    fn add<P: OsmPrimitive>(
+        &mut self,
+        element_type: ElementType,
+        primitive: &P,
+        (block, blob_index): (&pbf::PrimitiveBlock, usize),
+    ) -> Result<(), ElementError> {
+        let info = primitive.info();
+        let error = |kind| ElementError::new(primitive, blob_index, kind);
+
+        let version = info
+            .and_then(|info| info.version)
+            .ok_or_else(|| error(ElementErrorKind::MissingVersion))?;
+        let timestamp = info
+            .and_then(|info| info.timestamp)
+            .ok_or_else(|| error(ElementErrorKind::MissingTimestamp))?;
+
+        if normalize_timestamp(timestamp, block) > self.timestamp {
+            return Ok(());
+        }
+
+        let versions = match element_type {
+            ElementType::Node => &mut self.nodes,
+            ElementType::Way => &mut self.ways,
+            ElementType::Relation => &mut self.relations,
+        };
+
+        let entry = versions.entry(primitive.id()).or_insert((version, false));
+
+        if version >= entry.0 {
+            *entry = (version, !primitive.is_deleted());
+        }
+
+        Ok(())
+    }
+
+    /// Records the element versions of a block. Elements without a version or timestamp are skipped, they're reported
+    /// by [`Snapshot::check`] when they're imported.
+    fn process_primitive_block(&mut self, block: &pbf::PrimitiveBlock, blob_index: usize) -> anyhow::Result<()> {
+        for group in &block.primitivegroup {
+            if let Some(dense_nodes) = &group.dense {
+                for node in DenseNodeReader::new(dense_nodes)? {
+                    let _ = self.add(ElementType::Node, &node?, (block, blob_index));
+                }
+            }
+
+            for node in &group.nodes {
+                let _ = self.add(ElementType::Node, node, (block, blob_index));
+            }
+
+            for way in &group.ways {
+                let _ = self.add(ElementType::Way, way, (block, blob_index));
+            }
+
+            for relation in &group.relations {
+                let _ = self.add(ElementType::Relation, relation, (block, blob_index));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a full-history input and determines the version of each element at the configured `snapshot_at` time: the
+/// newest version not newer than it. Elements whose version at that time is deleted are left out. Returns `None` if
+/// no snapshot is configured.
+pub fn read_snapshot(input_path: &Path, config: &Config) -> anyhow::Result<Option<Snapshot>> {
+    let Some(snapshot_at) = &config.snapshot_at else {
+        return Ok(None);
+    };
+
+    if config.history {
+        bail!("Snapshots are regular dumps, `history` can't be enabled for them");
+    }
+
+    println!("Selecting element versions at {snapshot_at}");
+
+    let mut snapshot = Snapshot {
+        timestamp: parse_timestamp(snapshot_at).context("Invalid `snapshot_at`")?,
+        ..Default::default()
+    };
+
+    pipeline::decode_blocks(
+        &mut File::open(input_path)?,
+        config.worker_threads,
+        |position, result| {
+            if let Ok(DecodedBlock::Primitive(primitive_block)) = result
+                && let Err(error) = snapshot.process_primitive_block(&primitive_block, position.index)
+                && !is_data_error(&error)
+            {
+                return Err(error);
+            }
+            Ok(()) // Errors of the input are reported during the import, when the block fails the same way
+        },
+    )?;
+
+    for versions in [&mut snapshot.nodes, &mut snapshot.ways, &mut snapshot.relations] {
+        versions.retain(|_, (_, visible)| *visible);
+    }
+
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+
+    fn way(id: i64, version: i32, timestamp: i64, visible: bool) -> pbf::Way {
+        pbf::Way {
+            id,
+            info: Some(pbf::Info {
+                version: Some(version),
+                timestamp: Some(timestamp),
+                visible: Some(visible),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn versions_at_time() {
+        let block = pbf::PrimitiveBlock::default();
+
+        let mut snapshot = Snapshot {
+            timestamp: 2000 * 1000,
+            ..Default::default()
+        };
+
+        let ways = [
+            way(1, 1, 1000, true),
+            way(1, 2, 2000, true),
+            way(1, 3, 3000, true),
+            way(2, 1, 1000, true),
+            way(2, 2, 1500, false),
+            way(3, 1, 2500, true),
+        ];
+
+        for way in &ways {
+            snapshot.add(ElementType::Way, way, (&block, 0)).unwrap();
+        }
+
+        snapshot.ways.retain(|_, (_, visible)| *visible);
+
+        let selected: Vec<bool> = ways
+            .iter()
+            .map(|way| snapshot.contains(ElementType::Way, way))
+            .collect();
+
+        assert_eq!(selected, [false, true, false, false, false, false]);
+        assert!(!snapshot.contains(ElementType::Node, &ways[1]));
+
+        let mut unversioned = way(4, 1, 1000, true);
+        unversioned.info.as_mut().unwrap().version = None;
+
+        let error = snapshot.add(ElementType::Way, &unversioned, (&block, 3)).unwrap_err();
+        assert!(matches!(error.kind, ElementErrorKind::MissingVersion));
+        assert!(is_data_error(&error.into()));
+    }
+
+    #[test]
+    fn elements_without_timestamps() {
+        let mut unstamped = way(1, 1, 1000, true);
+        unstamped.info.as_mut().unwrap().timestamp = None;
+
+        let block = pbf::PrimitiveBlock {
+            primitivegroup: vec![pbf::PrimitiveGroup {
+                ways: vec![unstamped.clone(), way(2, 1, 1000, true)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut snapshot = Snapshot {
+            timestamp: 2000 * 1000,
+            ..Default::default()
+        };
+        snapshot.process_primitive_block(&block, 0).unwrap();
+
+        // Only the invalid element is skipped, the rest of its block is still recorded
+        assert!(!snapshot.contains(ElementType::Way, &unstamped));
+        assert!(snapshot.contains(ElementType::Way, &block.primitivegroup[0].ways[1]));
+
+        let error = snapshot.check(&unstamped, 0).unwrap_err();
+        assert!(matches!(error.kind, ElementErrorKind::MissingTimestamp));
+        assert!(snapshot.check(&block.primitivegroup[0].ways[1], 0).is_ok());
+    }
+}